@@ -0,0 +1,108 @@
+/*
+ * The MIT License (MIT)
+ *
+ * Copyright (c) 2022 Randoooom
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in the
+ * Software without restriction, including without limitation the rights to use, copy,
+ * modify, merge, publish, distribute, sublicense, and/or sell copies of the Software,
+ * and to permit persons to whom the Software is furnished to do so, subject to the
+ * following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+ * EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+ * MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+ * NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT
+ * HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR
+ * IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ *  SOFTWARE.
+ */
+
+use crate::database::client::{hash_password, verify_hash};
+use rbatis::crud::CRUD;
+use rbatis::rbatis::Rbatis;
+use rbatis::{TimestampZ, Uuid};
+
+/// The methods a relying party can authenticate with at the token endpoint
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthMethod {
+    /// client_id and client_secret via HTTP basic authentication
+    ClientSecretBasic,
+    /// client_id and client_secret in the request body
+    ClientSecretPost,
+    /// Public clients without any secret
+    None,
+    /// A PASETO client assertion signed with the registered ed25519 key
+    /// (the PASETO equivalent of private_key_jwt)
+    PrivateKeyPaseto,
+    /// A CA issued client certificate with the registered subject (RFC 8705)
+    TlsClientAuth,
+    /// A self signed client certificate with the registered thumbprint (RFC 8705)
+    SelfSignedTlsClientAuth,
+}
+
+/// A registered relying party
+#[derive(TypedBuilder, Clone, Debug, Getters, Setters, PartialEq)]
+#[crud_table(id_name: "client_id" | id_type: "Uuid" | table_name: "applications")]
+#[get = "pub"]
+#[set = "pub"]
+#[builder(field_defaults(setter(into)))]
+pub struct Application {
+    /// The unique client identifier
+    #[builder(default_code = r#"Uuid::new()"#)]
+    client_id: Uuid,
+    /// The displayable name
+    name: String,
+    /// The registered redirect uri
+    redirect_uri: String,
+    /// The hashed client secret (only for the client_secret methods)
+    #[builder(default, setter(transform = |secret: Option<String>| secret.map(hash_password)))]
+    client_secret: Option<String>,
+    /// The only method the application is allowed to authenticate with
+    auth_method: AuthMethod,
+    /// The ed25519 public key as PASERK (k4.public) used for client assertions
+    #[builder(default)]
+    public_key: Option<String>,
+    /// The expected subject DN of the client certificate (tls_client_auth)
+    #[builder(default)]
+    tls_subject_dn: Option<String>,
+    /// The x5t#S256 thumbprint of the self signed client certificate
+    #[builder(default)]
+    tls_thumbprint: Option<String>,
+    #[builder(default_code = r#"TimestampZ::now()"#)]
+    created_at: TimestampZ,
+}
+
+impl Application {
+    /// Get the application by its client_id
+    pub async fn from_client_id(
+        client_id: &Uuid,
+        connection: &Rbatis,
+    ) -> rbatis::Result<Option<Self>> {
+        // collect
+        connection.fetch_by_column("client_id", client_id).await
+    }
+
+    /// Generates a new random client secret
+    pub fn gen_secret() -> String {
+        // generate random bytes
+        let mut bytes = [0u8; 32];
+        openssl::rand::rand_bytes(&mut bytes).unwrap();
+
+        base64::encode_config(bytes.as_slice(), base64::URL_SAFE_NO_PAD)
+    }
+
+    /// Verify the given secret with the stored hash
+    pub fn verify_secret(&self, secret: &str) -> bool {
+        match &self.client_secret {
+            Some(hash) => verify_hash(hash.as_str(), secret),
+            None => false,
+        }
+    }
+}
@@ -0,0 +1,182 @@
+/*
+ * The MIT License (MIT)
+ *
+ * Copyright (c) 2022 Randoooom
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in the
+ * Software without restriction, including without limitation the rights to use, copy,
+ * modify, merge, publish, distribute, sublicense, and/or sell copies of the Software,
+ * and to permit persons to whom the Software is furnished to do so, subject to the
+ * following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+ * EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+ * MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+ * NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT
+ * HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR
+ * IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ *  SOFTWARE.
+ */
+
+use crate::{LOGIN_DELAY_AFTER, LOGIN_LOCK_AFTER, LOGIN_LOCK_DURATION};
+use chrono::{Duration, Utc};
+use rbatis::crud::CRUD;
+use rbatis::rbatis::Rbatis;
+use rbatis::{TimestampZ, Uuid};
+use std::net::IpAddr;
+
+/// The maximum delay between two attempts before the lock (in seconds)
+const MAX_DELAY: i64 = 5 * 60;
+/// Sources get more attempts, because many users can share one address
+const ADDRESS_FACTOR: i32 = 5;
+
+/// The failed logins of an account or a source address
+#[derive(TypedBuilder, Clone, Debug, Getters)]
+#[crud_table(id_name: "key" | id_type: "String" | table_name: "login_attempts")]
+#[get = "pub"]
+pub struct LoginAttempts {
+    /// `client:<sub>` or `address:<ip>`
+    key: String,
+    #[builder(default)]
+    failures: i32,
+    #[builder(default_code = r#"TimestampZ::now()"#)]
+    last_failure: TimestampZ,
+    #[builder(default)]
+    locked_until: Option<TimestampZ>,
+}
+
+impl LoginAttempts {
+    pub fn client_key(sub: &Uuid) -> String {
+        format!("client:{}", sub)
+    }
+
+    pub fn address_key(address: &IpAddr) -> String {
+        format!("address:{}", address)
+    }
+
+    /// Get the attempts of the key
+    pub async fn get(key: &str, connection: &Rbatis) -> rbatis::Result<Option<Self>> {
+        connection.fetch_by_column("key", key).await
+    }
+
+    /// The thresholds (delay, lock) of this key
+    fn thresholds(&self) -> (i32, i32) {
+        match self.key.starts_with("address:") {
+            true => (
+                *LOGIN_DELAY_AFTER * ADDRESS_FACTOR,
+                *LOGIN_LOCK_AFTER * ADDRESS_FACTOR,
+            ),
+            false => (*LOGIN_DELAY_AFTER, *LOGIN_LOCK_AFTER),
+        }
+    }
+
+    /// Is the key locked (not just delayed)?
+    pub fn is_locked(&self) -> bool {
+        self.locked_until
+            .as_ref()
+            .map(|until| until.inner > Utc::now())
+            .unwrap_or(false)
+    }
+
+    /// The seconds until the next attempt is allowed
+    pub fn retry_after(&self) -> Option<i64> {
+        let now = Utc::now();
+
+        // locked
+        if let Some(until) = &self.locked_until {
+            if until.inner > now {
+                return Some((until.inner - now).num_seconds().max(1));
+            }
+        }
+
+        // the delay doubles with every failure after the threshold
+        let (delay_after, _) = self.thresholds();
+        if self.failures < delay_after {
+            return None;
+        }
+        let delay = 1i64
+            .checked_shl((self.failures - delay_after) as u32)
+            .unwrap_or(MAX_DELAY)
+            .min(MAX_DELAY);
+        let next = self.last_failure.inner + Duration::seconds(delay);
+
+        (next > now).then(|| (next - now).num_seconds().max(1))
+    }
+
+    /// Record a failed login for the key, returns the attempts and if the key got locked by it
+    pub async fn fail(key: String, connection: &Rbatis) -> rbatis::Result<(Self, bool)> {
+        let (mut attempts, exists) = match Self::get(key.as_str(), connection).await? {
+            Some(attempts) => (attempts, true),
+            None => (Self::builder().key(key).build(), false),
+        };
+
+        // start over after an expired lock
+        if attempts.locked_until.is_some() && !attempts.is_locked() {
+            attempts.failures = 0;
+            attempts.locked_until = None;
+        }
+
+        attempts.failures += 1;
+        attempts.last_failure = TimestampZ::now();
+        let (_, lock_after) = attempts.thresholds();
+        let locked = attempts.failures >= lock_after && attempts.locked_until.is_none();
+        if locked {
+            attempts.locked_until = Some(TimestampZ::from(
+                Utc::now() + Duration::minutes(*LOGIN_LOCK_DURATION),
+            ));
+        }
+
+        if exists {
+            connection.update_by_column("key", &attempts).await?;
+        } else {
+            connection.save(&attempts, &[]).await?;
+        }
+
+        Ok((attempts, locked))
+    }
+
+    /// Forget the failures of the key (successful login or unlock)
+    pub async fn reset(key: &str, connection: &Rbatis) -> rbatis::Result<()> {
+        connection.remove_by_column::<Self, _>("key", key).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delay() {
+        let mut attempts = LoginAttempts::builder()
+            .key(LoginAttempts::client_key(&Uuid::new()))
+            .build();
+        assert!(attempts.retry_after().is_none());
+
+        // delayed after the threshold
+        attempts.failures = *LOGIN_DELAY_AFTER;
+        assert!(attempts.retry_after().is_some());
+        assert!(!attempts.is_locked());
+
+        // locked
+        attempts.locked_until = Some(TimestampZ::from(Utc::now() + Duration::minutes(10)));
+        assert!(attempts.is_locked());
+        assert!(attempts.retry_after().unwrap() > MAX_DELAY);
+    }
+
+    #[test]
+    fn test_address_thresholds() {
+        let mut attempts = LoginAttempts::builder()
+            .key(LoginAttempts::address_key(&"127.0.0.1".parse().unwrap()))
+            .build();
+
+        // more failures are allowed for addresses
+        attempts.failures = *LOGIN_DELAY_AFTER;
+        assert!(attempts.retry_after().is_none());
+    }
+}
@@ -23,8 +23,15 @@
  *  SOFTWARE.
  */
 
-use crate::TOTP_NAME;
+use crate::database::credential::WebauthnCredential;
+use crate::database::phone::PhoneVerificationCode;
+use crate::database::recovery::RecoveryCode;
+use crate::{
+    PASSWORD_HASH_ITERATIONS, PASSWORD_HASH_MEMORY, PASSWORD_HASH_PARALLELISM, PASSWORD_PEPPERS,
+    TOTP_NAME, TOTP_SKEW,
+};
 use argon2::{self};
+use chrono::{Duration, Utc};
 use google_authenticator::{ErrorCorrectionLevel, GoogleAuthenticator};
 use rbatis::crud::CRUD;
 use rbatis::rbatis::Rbatis;
@@ -80,7 +87,7 @@ pub struct Client {
     locale: String,
     /// The phone number of the user
     phone_number: Option<String>,
-    /// Not implemented yet
+    /// is the phone number verified?
     #[builder(default = false)]
     phone_number_verified: bool,
     /// last updated timestamp
@@ -98,6 +105,33 @@ impl Client {
         connection.fetch_by_column("nickname", nickname).await
     }
 
+    /// Get the client by the email (ignoring the case)
+    pub async fn from_email(email: &str, connection: &Rbatis) -> rbatis::Result<Option<Self>> {
+        // collect
+        let clients: Vec<Self> = connection
+            .fetch(
+                "SELECT * FROM clients WHERE lower(email) = lower($1) LIMIT 1",
+                vec![rbson::to_bson(email).unwrap()],
+            )
+            .await?;
+
+        Ok(clients.into_iter().next())
+    }
+
+    /// Get the client by the nickname or the email, returns whether the email matched
+    pub async fn from_identifier(
+        identifier: &str,
+        connection: &Rbatis,
+    ) -> rbatis::Result<Option<(Self, bool)>> {
+        if let Some(client) = Self::from_nickname(identifier, connection).await? {
+            return Ok(Some((client, false)));
+        }
+
+        Ok(Self::from_email(identifier, connection)
+            .await?
+            .map(|client| (client, true)))
+    }
+
     /// Get the associated address object of the user
     pub async fn address(&self, connection: &Rbatis) -> rbatis::Result<Option<Address>> {
         // collect
@@ -113,6 +147,44 @@ impl Client {
         connection.fetch_by_column("client", self.sub.clone()).await
     }
 
+    /// Get the verification tokens of the user with the given purpose
+    pub async fn verification_tokens(
+        &self,
+        purpose: TokenPurpose,
+        connection: &Rbatis,
+    ) -> rbatis::Result<Vec<ClientVerificationToken>> {
+        // collect
+        let tokens: Vec<ClientVerificationToken> = connection
+            .fetch_list_by_column("client", &[self.sub.clone()])
+            .await?;
+
+        Ok(tokens
+            .into_iter()
+            .filter(|token| token.purpose() == &purpose)
+            .collect())
+    }
+
+    /// The personal data a password should not be based on
+    pub fn user_inputs(&self) -> Vec<&str> {
+        let mut inputs = vec![
+            self.nickname.as_str(),
+            self.name.as_str(),
+            self.given_name.as_str(),
+            self.family_name.as_str(),
+            self.preferred_username.as_str(),
+            self.email.as_str(),
+        ];
+        if let Some(middle_name) = &self.middle_name {
+            inputs.push(middle_name.as_str());
+        }
+        // the local part of the email on its own
+        if let Some((local, _)) = self.email.split_once('@') {
+            inputs.push(local);
+        }
+
+        inputs
+    }
+
     /// Delete the current client
     pub async fn delete(self, connection: &Rbatis) {
         // remove the associated data
@@ -128,6 +200,17 @@ impl Client {
             .remove_by_column::<ClientVerificationToken, _>("client", self.sub.clone())
             .await
             .unwrap();
+        connection
+            .remove_by_column::<PhoneVerificationCode, _>("client", self.sub.clone())
+            .await
+            .unwrap();
+        RecoveryCode::invalidate(&self.sub, connection)
+            .await
+            .unwrap();
+        connection
+            .remove_by_column::<WebauthnCredential, _>("client", self.sub.clone())
+            .await
+            .unwrap();
 
         // remove the client
         connection
@@ -162,19 +245,107 @@ pub struct Address {
     client: Uuid,
 }
 
-pub fn hash_password(password: String) -> String {
-    // build the argon config
-    let config = argon2::Config {
-        variant: argon2::Variant::Argon2d,
+/// The length of a totp time step (in seconds)
+const TOTP_STEP: i64 = 30;
+
+/// The prefix of peppered hashes, followed by the version of the pepper
+const PEPPER_PREFIX: &str = "$pepper=";
+
+/// A versioned secret mixed into the hashes
+type Pepper = (u32, Vec<u8>);
+
+/// The pepper used for new hashes (the highest version)
+fn current_pepper() -> Option<&'static Pepper> {
+    PASSWORD_PEPPERS.iter().max_by_key(|(version, _)| *version)
+}
+
+/// The argon2id config with the configured costs and the pepper as secret
+fn hash_config(pepper: Option<&Pepper>) -> argon2::Config {
+    argon2::Config {
+        variant: argon2::Variant::Argon2id,
+        mem_cost: *PASSWORD_HASH_MEMORY,
+        time_cost: *PASSWORD_HASH_ITERATIONS,
+        lanes: *PASSWORD_HASH_PARALLELISM,
+        secret: pepper.map(|(_, secret)| secret.as_slice()).unwrap_or(&[]),
         ..Default::default()
-    };
+    }
+}
+
+/// Split the pepper version from the argon2 hash
+fn split_pepper(hash: &str) -> Option<(Option<u32>, &str)> {
+    match hash.strip_prefix(PEPPER_PREFIX) {
+        Some(rest) => {
+            let (version, encoded) = rest.split_at(rest.find('$')?);
+            Some((Some(version.parse().ok()?), encoded))
+        }
+        None => Some((None, hash)),
+    }
+}
+
+fn hash_with(password: &str, pepper: Option<&Pepper>) -> String {
     // gen the salt
     let mut salt = [0u8; 16];
     // fill
     openssl::rand::rand_bytes(&mut salt).unwrap();
 
     // hash the password
-    argon2::hash_encoded(password.as_bytes(), salt.as_slice(), &config).unwrap()
+    let encoded =
+        argon2::hash_encoded(password.as_bytes(), salt.as_slice(), &hash_config(pepper)).unwrap();
+    match pepper {
+        Some((version, _)) => format!("{}{}{}", PEPPER_PREFIX, version, encoded),
+        None => encoded,
+    }
+}
+
+fn verify_with(hash: &str, password: &str, peppers: &[Pepper]) -> bool {
+    let (version, encoded) = match split_pepper(hash) {
+        Some(split) => split,
+        None => return false,
+    };
+    // the pepper of the hash has to be known
+    let secret = match version {
+        Some(version) => match peppers.iter().find(|(known, _)| *known == version) {
+            Some((_, secret)) => secret.as_slice(),
+            None => return false,
+        },
+        None => &[],
+    };
+
+    argon2::verify_encoded_ext(encoded, password.as_bytes(), secret, &[]).unwrap_or(false)
+}
+
+fn needs_rehash_with(hash: &str, pepper: Option<&Pepper>) -> bool {
+    let config = hash_config(pepper);
+    let expected = format!(
+        "$argon2id$v={}$m={},t={},p={}$",
+        config.version.as_u32(),
+        config.mem_cost,
+        config.time_cost,
+        config.lanes
+    );
+
+    match split_pepper(hash) {
+        Some((version, encoded)) => {
+            version != pepper.map(|(current, _)| *current)
+                || !encoded.starts_with(expected.as_str())
+        }
+        None => true,
+    }
+}
+
+/// Hash the password (or any other secret) with the current pepper
+pub fn hash_password(password: String) -> String {
+    hash_with(password.as_str(), current_pepper())
+}
+
+/// Verify the password with a hash created by hash_password
+pub fn verify_hash(hash: &str, password: &str) -> bool {
+    verify_with(hash, password, PASSWORD_PEPPERS.as_slice())
+}
+
+/// Checks if the hash was created with another variant, other costs or another pepper than configured
+pub fn needs_rehash(hash: &str) -> bool {
+    needs_rehash_with(hash, current_pepper())
 }
 
 #[derive(TypedBuilder, Clone, Debug, Getters, Setters)]
@@ -186,7 +357,7 @@ pub struct ClientAuthenticationData {
     /// the unique identifier for the data
     #[builder(default_code = r#"Uuid::new()"#)]
     uuid: Uuid,
-    /// The argon2d hashed password
+    /// The argon2id hashed password
     #[builder(setter(!strip_option, transform = |password: String| hash_password(password)))]
     password: String,
     /// The TOTP secret (base32 encoded)
@@ -194,6 +365,9 @@ pub struct ClientAuthenticationData {
     secret: String,
     #[builder(default = false)]
     totp: bool,
+    /// The time step of the last accepted totp (it and all earlier ones are rejected)
+    #[builder(default)]
+    last_totp_step: i64,
     /// The last registered login / grant
     #[builder(default_code = r#"TimestampZ::now()"#)]
     last_login: TimestampZ,
@@ -217,21 +391,47 @@ impl ClientAuthenticationData {
     }
 
     /// Validate the given token with the totp secret of the client
-    pub fn validate_totp(&self, token: &str) -> bool {
+    /// The step of an accepted token is recorded, so the data has to be saved afterwards
+    pub fn validate_totp(&mut self, token: &str) -> bool {
         // init the instance
         let totp = GoogleAuthenticator::new();
+        let current = Utc::now().timestamp() / TOTP_STEP;
+        let skew = *TOTP_SKEW;
+
+        // only steps after the last accepted one are valid
+        for step in (current - skew).max(self.last_totp_step + 1)..=current + skew {
+            let code = match totp.get_code(self.secret.as_str(), step as u64) {
+                Ok(code) => code,
+                Err(_) => return false,
+            };
+
+            if code.len() == token.len() && openssl::memcmp::eq(code.as_bytes(), token.as_bytes()) {
+                self.last_totp_step = step;
+                return true;
+            }
+        }
+
+        false
+    }
 
-        // verify
-        totp.verify_code(self.secret.as_str(), token, 30, 0)
+    /// Verify the password with the hash
+    pub fn verify_password(&self, password: &str) -> bool {
+        verify_hash(self.password.as_str(), password)
     }
 
-    /// Authenticate the login for the client based on the given password (and totp, if enabled)
-    pub fn login(&self, password: &str, token: Option<&str>) -> bool {
-        // verify the password with the hash
-        let matches =
-            argon2::verify_encoded(self.password.as_str(), password.as_bytes()).unwrap_or(false);
+    /// Rehash the verified password if the hash is outdated, returns true if it changed
+    pub fn upgrade_hash(&mut self, password: &str) -> bool {
+        if !needs_rehash(self.password.as_str()) {
+            return false;
+        }
 
-        if matches {
+        self.password = hash_password(password.to_string());
+        true
+    }
+
+    /// Authenticate the login for the client based on the given password (and totp, if enabled)
+    pub fn login(&mut self, password: &str, token: Option<&str>) -> bool {
+        if self.verify_password(password) {
             // check for totp activated (secret exists or not)
             if self.totp {
                 // check the input
@@ -271,6 +471,35 @@ impl ClientAuthenticationData {
     // pub fn enable_totp(&mut self, code: &str, connection: &ConnectionPointer) -> bool {}
 }
 
+/// What a verification token can be redeemed for
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub enum TokenPurpose {
+    EmailVerification,
+    PasswordReset,
+    /// Confirms the new email stored in the payload
+    EmailChange,
+    /// Cancels the pending email change (sent to the old address)
+    EmailChangeCancel,
+    /// Passwordless login through a link
+    MagicLink,
+    /// Unlocks the account after too many failed logins
+    AccountUnlock,
+}
+
+impl TokenPurpose {
+    /// How long tokens of this purpose can be redeemed
+    pub fn lifetime(&self) -> Duration {
+        match self {
+            TokenPurpose::EmailVerification => Duration::hours(24),
+            TokenPurpose::PasswordReset => Duration::hours(1),
+            TokenPurpose::EmailChange => Duration::hours(24),
+            TokenPurpose::EmailChangeCancel => Duration::hours(24),
+            TokenPurpose::MagicLink => Duration::minutes(15),
+            TokenPurpose::AccountUnlock => Duration::hours(1),
+        }
+    }
+}
+
 #[derive(TypedBuilder, Clone, Debug, Getters)]
 #[crud_table(id_name: "uuid" | id_type: "Uuid" | table_name: "client_verification_tokens")]
 #[get = "pub"]
@@ -281,6 +510,63 @@ pub struct ClientVerificationToken {
     uuid: Uuid,
     /// the associated client
     client: Uuid,
+    /// what the token can be redeemed for
+    purpose: TokenPurpose,
+    #[builder(default_code = r#"TimestampZ::now()"#)]
+    created_at: TimestampZ,
+    /// the token can not be redeemed after this
+    expires_at: TimestampZ,
+    /// additional data depending on the purpose (like the new email)
+    #[builder(default)]
+    payload: Option<String>,
+}
+
+impl ClientVerificationToken {
+    /// Create a new token for the client which expires after the lifetime of the purpose
+    pub fn new(client: Uuid, purpose: TokenPurpose) -> Self {
+        Self::builder()
+            .client(client)
+            .expires_at(TimestampZ::from(Utc::now() + purpose.lifetime()))
+            .purpose(purpose)
+            .build()
+    }
+
+    /// Create a new token carrying the given payload
+    pub fn with_payload(client: Uuid, purpose: TokenPurpose, payload: String) -> Self {
+        Self::builder()
+            .client(client)
+            .expires_at(TimestampZ::from(Utc::now() + purpose.lifetime()))
+            .purpose(purpose)
+            .payload(Some(payload))
+            .build()
+    }
+
+    /// Checks if the token has not expired yet
+    pub fn is_active(&self) -> bool {
+        self.expires_at.inner >= Utc::now()
+    }
+
+    /// Redeem the token with the given purpose. The token is deleted on use.
+    pub async fn redeem(
+        uuid: &Uuid,
+        purpose: TokenPurpose,
+        connection: &Rbatis,
+    ) -> rbatis::Result<Option<Self>> {
+        // get the token
+        let token: Option<Self> = connection.fetch_by_column("uuid", uuid).await?;
+
+        match token {
+            Some(token) if token.purpose == purpose => {
+                // delete it, expired or not
+                connection
+                    .remove_by_column::<Self, _>("uuid", &token.uuid)
+                    .await?;
+
+                Ok(Some(token).filter(|token| token.is_active()))
+            }
+            _ => Ok(None),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -299,7 +585,7 @@ impl Default for Client {
             // TODO
             picture: "TODO".to_string(),
             website: None,
-            email: env!("TESTMAIL").to_string(),
+            email: "test@example.com".to_string(),
             email_verified: false,
             gender: Gender::Other,
             birthdate: "".to_string(),
@@ -339,6 +625,26 @@ mod tests {
         assert_eq!(client.sub, second.sub)
     }
 
+    #[tokio::test]
+    async fn test_from_identifier() {
+        let (connection, client) = setup().await;
+
+        // the email ignores the case
+        let (second, email) =
+            Client::from_identifier(client.email().to_uppercase().as_str(), &connection)
+                .await
+                .unwrap()
+                .unwrap();
+        assert_eq!(client.sub, second.sub);
+        assert!(email);
+
+        let (_, email) = Client::from_identifier(client.nickname(), &connection)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(!email);
+    }
+
     #[tokio::test]
     async fn test_associated() {
         let (connection, client) = setup().await;
@@ -356,4 +662,62 @@ mod tests {
         assert!(auth.is_some());
         assert_eq!(auth.unwrap().client(), client.sub());
     }
+
+    #[test]
+    fn test_needs_rehash() {
+        assert!(!needs_rehash(hash_password("password".into()).as_str()));
+
+        // the previous default
+        let config = argon2::Config {
+            variant: argon2::Variant::Argon2d,
+            ..Default::default()
+        };
+        let hash = argon2::hash_encoded(b"password", b"somesaltsomesalt", &config).unwrap();
+        assert!(needs_rehash(hash.as_str()));
+    }
+
+    #[test]
+    fn test_pepper() {
+        let first = (1, b"first pepper".to_vec());
+        let second = (2, b"second pepper".to_vec());
+
+        let hash = hash_with("password", Some(&first));
+        assert!(hash.starts_with("$pepper=1$argon2id$"));
+        assert!(verify_with(
+            hash.as_str(),
+            "password",
+            &[first.clone(), second.clone()]
+        ));
+        assert!(!verify_with(hash.as_str(), "wrong", &[first.clone()]));
+        // the pepper is required
+        assert!(!verify_with(hash.as_str(), "password", &[second.clone()]));
+        assert!(!verify_with(
+            hash.as_str().strip_prefix("$pepper=1").unwrap(),
+            "password",
+            &[first.clone()]
+        ));
+
+        // rotated
+        assert!(!needs_rehash_with(hash.as_str(), Some(&first)));
+        assert!(needs_rehash_with(hash.as_str(), Some(&second)));
+        assert!(needs_rehash_with(hash.as_str(), None));
+        let hash = hash_with("password", None);
+        assert!(verify_with(hash.as_str(), "password", &[]));
+        assert!(needs_rehash_with(hash.as_str(), Some(&first)));
+    }
+
+    #[test]
+    fn test_totp_replay() {
+        let mut auth = ClientAuthenticationData::builder()
+            .client(Uuid::new())
+            .password("password".into())
+            .build();
+        let token = GoogleAuthenticator::new()
+            .get_code(auth.secret().as_str(), 0)
+            .unwrap();
+
+        assert!(auth.validate_totp(token.as_str()));
+        // the same code can not be used again
+        assert!(!auth.validate_totp(token.as_str()));
+    }
 }
@@ -0,0 +1,85 @@
+/*
+ * The MIT License (MIT)
+ *
+ * Copyright (c) 2022 Randoooom
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in the
+ * Software without restriction, including without limitation the rights to use, copy,
+ * modify, merge, publish, distribute, sublicense, and/or sell copies of the Software,
+ * and to permit persons to whom the Software is furnished to do so, subject to the
+ * following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+ * EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+ * MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+ * NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT
+ * HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR
+ * IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ *  SOFTWARE.
+ */
+
+use rbatis::crud::CRUD;
+use rbatis::rbatis::Rbatis;
+use rbatis::{TimestampZ, Uuid};
+
+/// A registered webauthn authenticator (passkey or security key)
+#[derive(TypedBuilder, Clone, Debug, Getters, Setters)]
+#[crud_table(id_name: "uuid" | id_type: "Uuid" | table_name: "webauthn_credentials")]
+#[get = "pub"]
+#[set = "pub"]
+#[builder(field_defaults(setter(into)))]
+pub struct WebauthnCredential {
+    #[builder(default_code = r#"Uuid::new()"#)]
+    uuid: Uuid,
+    client: Uuid,
+    /// the displayable name chosen by the user
+    name: String,
+    /// the credential id (base64url encoded)
+    credential_id: String,
+    /// the DER encoded public key (base64 encoded)
+    public_key: String,
+    /// the COSE algorithm of the key
+    algorithm: i64,
+    /// the last seen signature counter
+    sign_count: i64,
+    /// the comma separated transports reported by the authenticator
+    #[builder(default)]
+    transports: String,
+    #[builder(default_code = r#"TimestampZ::now()"#)]
+    created_at: TimestampZ,
+    #[builder(default)]
+    last_used: Option<TimestampZ>,
+}
+
+impl WebauthnCredential {
+    /// Get the credential by its credential id
+    pub async fn from_credential_id(
+        credential_id: &str,
+        connection: &Rbatis,
+    ) -> rbatis::Result<Option<Self>> {
+        connection
+            .fetch_by_column("credential_id", credential_id)
+            .await
+    }
+
+    /// Get all credentials of the client
+    pub async fn of_client(client: &Uuid, connection: &Rbatis) -> rbatis::Result<Vec<Self>> {
+        connection
+            .fetch_list_by_column("client", &[client.clone()])
+            .await
+    }
+
+    /// The transports as list
+    pub fn transport_list(&self) -> Vec<String> {
+        self.transports
+            .split(',')
+            .filter(|transport| !transport.is_empty())
+            .map(str::to_string)
+            .collect()
+    }
+}
@@ -26,4 +26,11 @@
 DELETE FROM addresses;
 DELETE FROM client_authentication_data;
 DELETE FROM client_verification_tokens;
+DELETE FROM phone_verification_codes;
+DELETE FROM recovery_codes;
+DELETE FROM webauthn_credentials;
 DELETE FROM clients;
+DELETE FROM applications;
+DELETE FROM mail_outbox;
+DELETE FROM login_attempts;
+DELETE FROM signing_keys;
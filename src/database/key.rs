@@ -0,0 +1,44 @@
+/*
+ * The MIT License (MIT)
+ *
+ * Copyright (c) 2022 Randoooom
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in the
+ * Software without restriction, including without limitation the rights to use, copy,
+ * modify, merge, publish, distribute, sublicense, and/or sell copies of the Software,
+ * and to permit persons to whom the Software is furnished to do so, subject to the
+ * following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+ * EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+ * MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+ * NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT
+ * HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR
+ * IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ *  SOFTWARE.
+ */
+
+use crate::locator::paseto::KeyState;
+use rbatis::TimestampZ;
+
+#[derive(TypedBuilder, Clone, Debug, Getters)]
+#[crud_table(id_name: "pid" | id_type: "String" | table_name: "signing_keys")]
+#[get = "pub"]
+#[builder(field_defaults(setter(into)))]
+pub struct StoredSigningKey {
+    /// The PASERK pid of the public key
+    pid: String,
+    /// The encrypted raw private key (base64 encoded)
+    private_key: String,
+    /// the state in the rotation
+    state: KeyState,
+    /// The moment the key got retired
+    retired_at: Option<TimestampZ>,
+    #[builder(default_code = r#"TimestampZ::now()"#)]
+    created_at: TimestampZ,
+}
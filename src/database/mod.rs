@@ -25,7 +25,14 @@
 
 use rbatis::rbatis::Rbatis;
 
+pub mod application;
+pub mod attempts;
 pub mod client;
+pub mod credential;
+pub mod key;
+pub mod outbox;
+pub mod phone;
+pub mod recovery;
 
 /// Establish the postgres connection with the env vars
 pub async fn establish_connection() -> Rbatis {
@@ -44,6 +51,14 @@ pub async fn establish_connection() -> Rbatis {
     rbatis
 }
 
+/// Checks if the error is a unique violation (SQLSTATE 23505) of the given constraint
+pub fn unique_violation(error: &rbatis::Error, constraint: &str) -> bool {
+    let message = error.to_string();
+
+    message.contains("duplicate key value violates unique constraint")
+        && message.contains(format!("\"{}\"", constraint).as_str())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -53,4 +68,17 @@ mod tests {
         // would panic here on failure, because of the unwraps
         let _ = establish_connection().await;
     }
+
+    #[test]
+    fn test_unique_violation() {
+        let error = rbatis::Error::from(
+            "error returned from database: duplicate key value violates unique constraint \"clients_email_key\"",
+        );
+        assert!(unique_violation(&error, "clients_email_key"));
+        assert!(!unique_violation(&error, "clients_nickname_key"));
+        assert!(!unique_violation(
+            &rbatis::Error::from("connection refused"),
+            "clients_email_key"
+        ));
+    }
 }
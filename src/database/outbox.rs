@@ -0,0 +1,215 @@
+/*
+ * The MIT License (MIT)
+ *
+ * Copyright (c) 2022 Randoooom
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in the
+ * Software without restriction, including without limitation the rights to use, copy,
+ * modify, merge, publish, distribute, sublicense, and/or sell copies of the Software,
+ * and to permit persons to whom the Software is furnished to do so, subject to the
+ * following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+ * EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+ * MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+ * NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT
+ * HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR
+ * IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ *  SOFTWARE.
+ */
+
+use crate::locator::mail::MailOptions;
+use chrono::{Duration, Utc};
+use rbatis::crud::CRUD;
+use rbatis::rbatis::Rbatis;
+use rbatis::{TimestampZ, Uuid};
+
+/// The delay before the first retry (in seconds), doubled with every failed attempt
+const RETRY_DELAY: i64 = 30;
+/// The maximum delay between two attempts (in seconds)
+const MAX_RETRY_DELAY: i64 = 60 * 60;
+
+/// The delivery state of a queued mail
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub enum MailStatus {
+    /// waiting for the (next) delivery attempt
+    Pending,
+    Sent,
+    /// gave up after too many attempts
+    Failed,
+}
+
+#[derive(TypedBuilder, Clone, Debug, Getters, Serialize, Deserialize)]
+#[crud_table(id_name: "uuid" | id_type: "Uuid" | table_name: "mail_outbox")]
+#[get = "pub"]
+pub struct OutgoingMail {
+    #[builder(default_code = r#"Uuid::new()"#)]
+    uuid: Uuid,
+    recipient: String,
+    subject: String,
+    html: String,
+    text: String,
+    #[builder(default_code = r#"MailStatus::Pending"#)]
+    status: MailStatus,
+    #[builder(default)]
+    attempts: i32,
+    /// the error of the last failed attempt
+    #[builder(default)]
+    last_error: Option<String>,
+    #[builder(default_code = r#"TimestampZ::now()"#)]
+    next_attempt: TimestampZ,
+    #[builder(default)]
+    sent_at: Option<TimestampZ>,
+    #[builder(default_code = r#"TimestampZ::now()"#)]
+    created_at: TimestampZ,
+}
+
+impl OutgoingMail {
+    /// Put the mail into the outbox, it gets delivered by the MailWorker
+    pub async fn queue(options: MailOptions, connection: &Rbatis) -> rbatis::Result<Self> {
+        let mail = Self::builder()
+            .recipient(options.to().clone())
+            .subject(options.subject().clone())
+            .html(options.html().clone())
+            .text(options.text().clone())
+            .build();
+        connection.save(&mail, &[]).await?;
+
+        Ok(mail)
+    }
+
+    /// Fetch the pending mails which are due for delivery
+    pub async fn due(connection: &Rbatis) -> rbatis::Result<Vec<Self>> {
+        connection
+            .fetch(
+                "SELECT * FROM mail_outbox WHERE status = $1 AND next_attempt <= now() ORDER BY next_attempt",
+                vec![rbson::to_bson(&MailStatus::Pending).unwrap()],
+            )
+            .await
+    }
+
+    /// Convert the mail back into options for the transport
+    pub fn options(&self) -> MailOptions {
+        MailOptions::builder()
+            .to(self.recipient.clone())
+            .subject(self.subject.clone())
+            .html(self.html.clone())
+            .text(self.text.clone())
+            .build()
+    }
+
+    /// Mark the mail as delivered
+    pub fn sent(&mut self) {
+        self.attempts += 1;
+        self.status = MailStatus::Sent;
+        self.last_error = None;
+        self.sent_at = Some(TimestampZ::now());
+        self.clear_content();
+    }
+
+    /// Record the failed attempt and schedule the next one with exponential backoff
+    pub fn failed(&mut self, error: String, max_attempts: i32) {
+        self.attempts += 1;
+        self.last_error = Some(error);
+
+        if self.attempts >= max_attempts {
+            self.status = MailStatus::Failed;
+            self.clear_content();
+            return;
+        }
+
+        let delay = RETRY_DELAY
+            .saturating_mul(1i64 << (self.attempts - 1).min(20))
+            .min(MAX_RETRY_DELAY);
+        self.next_attempt = TimestampZ::from(Utc::now() + Duration::seconds(delay));
+    }
+
+    /// Drop the bodies once they are not needed anymore, they may contain live tokens
+    fn clear_content(&mut self) {
+        self.html.clear();
+        self.text.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mail() -> OutgoingMail {
+        OutgoingMail::builder()
+            .recipient("test@example.com".to_string())
+            .subject("Subject".to_string())
+            .html("<p>Content</p>".to_string())
+            .text("Content".to_string())
+            .build()
+    }
+
+    #[test]
+    fn test_backoff() {
+        let mut mail = mail();
+
+        mail.failed("Connection refused".to_string(), 5);
+        let first = mail.next_attempt().inner - Utc::now();
+        mail.failed("Connection refused".to_string(), 5);
+        let second = mail.next_attempt().inner - Utc::now();
+
+        // the delay doubles
+        assert!(second > first + Duration::seconds(RETRY_DELAY / 2));
+        assert_eq!(mail.status(), &MailStatus::Pending);
+        assert_eq!(mail.last_error().as_deref(), Some("Connection refused"));
+    }
+
+    #[test]
+    fn test_give_up() {
+        let mut mail = mail();
+
+        for _ in 0..3 {
+            mail.failed("Connection refused".to_string(), 3);
+        }
+        assert_eq!(mail.status(), &MailStatus::Failed);
+        assert_eq!(*mail.attempts(), 3);
+        // only the metadata is kept
+        assert!(mail.html().is_empty());
+        assert!(mail.text().is_empty());
+        assert_eq!(mail.recipient(), "test@example.com");
+    }
+
+    #[test]
+    fn test_sent() {
+        let mut mail = mail();
+
+        // the content is kept for the retries
+        mail.failed("Connection refused".to_string(), 3);
+        assert_eq!(mail.text(), "Content");
+
+        mail.sent();
+        assert_eq!(mail.status(), &MailStatus::Sent);
+        assert!(mail.html().is_empty());
+        assert!(mail.text().is_empty());
+        assert_eq!(mail.subject(), "Subject");
+    }
+
+    #[tokio::test]
+    async fn test_queue() {
+        let connection = crate::database::establish_connection().await;
+        let queued = OutgoingMail::queue(mail().options(), &connection)
+            .await
+            .unwrap();
+
+        // immediately due
+        let due = OutgoingMail::due(&connection).await.unwrap();
+        assert!(due.iter().any(|mail| mail.uuid() == queued.uuid()));
+
+        // scheduled for a later retry
+        let mut later = mail();
+        later.failed("Connection refused".to_string(), 5);
+        connection.save(&later, &[]).await.unwrap();
+        let due = OutgoingMail::due(&connection).await.unwrap();
+        assert!(!due.iter().any(|mail| mail.uuid() == later.uuid()));
+    }
+}
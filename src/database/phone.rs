@@ -0,0 +1,121 @@
+/*
+ * The MIT License (MIT)
+ *
+ * Copyright (c) 2022 Randoooom
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in the
+ * Software without restriction, including without limitation the rights to use, copy,
+ * modify, merge, publish, distribute, sublicense, and/or sell copies of the Software,
+ * and to permit persons to whom the Software is furnished to do so, subject to the
+ * following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+ * EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+ * MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+ * NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT
+ * HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR
+ * IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ *  SOFTWARE.
+ */
+
+use chrono::{Duration, Utc};
+use rbatis::{TimestampZ, Uuid};
+
+/// The lifetime of the codes (in minutes)
+const CODE_LIFETIME: i64 = 10;
+/// The number of wrong guesses before the code is invalidated
+pub const MAX_ATTEMPTS: i32 = 5;
+
+/// A pending verification of the phone number (one per client)
+#[derive(TypedBuilder, Clone, Debug, Getters)]
+#[crud_table(id_name: "client" | id_type: "Uuid" | table_name: "phone_verification_codes")]
+#[get = "pub"]
+pub struct PhoneVerificationCode {
+    client: Uuid,
+    /// the number the code was sent to
+    phone_number: String,
+    /// the sha256 hash of the code (base64 encoded)
+    code: String,
+    #[builder(default)]
+    attempts: i32,
+    #[builder(default_code = r#"TimestampZ::now()"#)]
+    created_at: TimestampZ,
+    #[builder(default_code = r#"TimestampZ::from(Utc::now() + Duration::minutes(CODE_LIFETIME))"#)]
+    expires_at: TimestampZ,
+}
+
+impl PhoneVerificationCode {
+    /// Generate a new random 6 digit code, returns the stored object and the plain code
+    pub fn generate(client: Uuid, phone_number: String) -> (Self, String) {
+        let mut bytes = [0u8; 4];
+        openssl::rand::rand_bytes(&mut bytes).unwrap();
+        let code = format!("{:06}", u32::from_be_bytes(bytes) % 1_000_000);
+
+        (
+            Self::builder()
+                .client(client)
+                .phone_number(phone_number)
+                .code(Self::hash(code.as_str()))
+                .build(),
+            code,
+        )
+    }
+
+    fn hash(code: &str) -> String {
+        base64::encode(openssl::sha::sha256(code.as_bytes()))
+    }
+
+    /// Checks if the code is not expired and has guesses left
+    pub fn is_active(&self) -> bool {
+        self.expires_at.inner >= Utc::now() && self.attempts < MAX_ATTEMPTS
+    }
+
+    /// Verify the given code, wrong guesses are counted
+    pub fn verify(&mut self, code: &str) -> bool {
+        if !self.is_active() {
+            return false;
+        }
+
+        let hashed = Self::hash(code.trim());
+        let matches = hashed.len() == self.code.len()
+            && openssl::memcmp::eq(hashed.as_bytes(), self.code.as_bytes());
+        if !matches {
+            self.attempts += 1;
+        }
+
+        matches
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verify() {
+        let (mut stored, code) =
+            PhoneVerificationCode::generate(Uuid::new(), "+49123456789".to_string());
+        assert_eq!(code.len(), 6);
+
+        assert!(!stored.verify("wrong"));
+        assert_eq!(*stored.attempts(), 1);
+        assert!(stored.verify(code.as_str()));
+    }
+
+    #[test]
+    fn test_attempts() {
+        let (mut stored, code) =
+            PhoneVerificationCode::generate(Uuid::new(), "+49123456789".to_string());
+
+        for _ in 0..MAX_ATTEMPTS {
+            stored.verify("wrong");
+        }
+        // locked even for the right code
+        assert!(!stored.verify(code.as_str()));
+    }
+}
@@ -0,0 +1,139 @@
+/*
+ * The MIT License (MIT)
+ *
+ * Copyright (c) 2022 Randoooom
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in the
+ * Software without restriction, including without limitation the rights to use, copy,
+ * modify, merge, publish, distribute, sublicense, and/or sell copies of the Software,
+ * and to permit persons to whom the Software is furnished to do so, subject to the
+ * following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+ * EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+ * MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+ * NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT
+ * HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR
+ * IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ *  SOFTWARE.
+ */
+
+use crate::database::client::{hash_password, verify_hash};
+use rbatis::crud::CRUD;
+use rbatis::rbatis::Rbatis;
+use rbatis::{TimestampZ, Uuid};
+
+/// The number of codes generated at once
+pub const CODE_COUNT: usize = 10;
+/// The characters used for the codes (without ambiguous ones like 0/o and 1/l)
+const ALPHABET: &[u8] = b"23456789abcdefghjkmnpqrstuvwxyz";
+
+/// A single-use code which can be used in place of the totp
+#[derive(TypedBuilder, Clone, Debug, Getters)]
+#[crud_table(id_name: "uuid" | id_type: "Uuid" | table_name: "recovery_codes")]
+#[get = "pub"]
+pub struct RecoveryCode {
+    #[builder(default_code = r#"Uuid::new()"#)]
+    uuid: Uuid,
+    client: Uuid,
+    /// the argon2 hash of the normalized code
+    code: String,
+    #[builder(default_code = r#"TimestampZ::now()"#)]
+    created_at: TimestampZ,
+}
+
+impl RecoveryCode {
+    /// Generate a new random code, returns the stored object and the plain code
+    pub fn generate(client: Uuid) -> (Self, String) {
+        let mut bytes = [0u8; 10];
+        openssl::rand::rand_bytes(&mut bytes).unwrap();
+        let code = bytes
+            .iter()
+            .map(|byte| ALPHABET[*byte as usize % ALPHABET.len()] as char)
+            .collect::<String>();
+        // group for readability
+        let code = format!("{}-{}", &code[..5], &code[5..]);
+
+        (
+            Self::builder()
+                .client(client)
+                .code(hash_password(Self::normalize(code.as_str())))
+                .build(),
+            code,
+        )
+    }
+
+    /// Ignore case, whitespace and the separator
+    fn normalize(code: &str) -> String {
+        code.chars()
+            .filter(|c| c.is_ascii_alphanumeric())
+            .map(|c| c.to_ascii_lowercase())
+            .collect()
+    }
+
+    /// Checks the given code against the hash
+    pub fn matches(&self, code: &str) -> bool {
+        verify_hash(self.code.as_str(), Self::normalize(code).as_str())
+    }
+
+    /// Replace all codes of the client with a new set, returns the plain codes
+    pub async fn regenerate(client: &Uuid, connection: &Rbatis) -> rbatis::Result<Vec<String>> {
+        Self::invalidate(client, connection).await?;
+
+        let mut codes = Vec::with_capacity(CODE_COUNT);
+        for _ in 0..CODE_COUNT {
+            let (stored, code) = Self::generate(client.clone());
+            connection.save(&stored, &[]).await?;
+            codes.push(code);
+        }
+
+        Ok(codes)
+    }
+
+    /// Remove all codes of the client
+    pub async fn invalidate(client: &Uuid, connection: &Rbatis) -> rbatis::Result<()> {
+        connection
+            .remove_by_column::<Self, _>("client", client)
+            .await?;
+        Ok(())
+    }
+
+    /// Consume the matching code of the client, returns false if none matched
+    pub async fn redeem(client: &Uuid, code: &str, connection: &Rbatis) -> rbatis::Result<bool> {
+        let codes: Vec<Self> = connection
+            .fetch_list_by_column("client", &[client.clone()])
+            .await?;
+
+        match codes.into_iter().find(|stored| stored.matches(code)) {
+            Some(stored) => {
+                // single use
+                connection
+                    .remove_by_column::<Self, _>("uuid", stored.uuid())
+                    .await?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches() {
+        let (stored, code) = RecoveryCode::generate(Uuid::new());
+        assert_eq!(code.len(), 11);
+
+        assert!(stored.matches(code.as_str()));
+        // the input is normalized
+        assert!(stored.matches(code.to_uppercase().replace('-', " ").as_str()));
+        assert!(!stored.matches("aaaaa-aaaaa"));
+    }
+}
@@ -73,3 +73,89 @@ CREATE TABLE IF NOT EXISTS client_verification_tokens
     uuid   uuid PRIMARY KEY DEFAULT gen_random_uuid(),
     client uuid NOT NULL REFERENCES clients (sub)
 );
+
+ALTER TABLE client_authentication_data
+    ADD COLUMN IF NOT EXISTS last_totp_step bigint NOT NULL DEFAULT 0;
+
+ALTER TABLE client_verification_tokens
+    ADD COLUMN IF NOT EXISTS purpose    varchar(255) NOT NULL DEFAULT 'EmailVerification',
+    ADD COLUMN IF NOT EXISTS created_at timestamptz  NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    ADD COLUMN IF NOT EXISTS expires_at timestamptz  NOT NULL DEFAULT CURRENT_TIMESTAMP + INTERVAL '1 day',
+    ADD COLUMN IF NOT EXISTS payload    varchar(255) NULL;
+
+CREATE TABLE IF NOT EXISTS applications
+(
+    client_id      uuid PRIMARY KEY      DEFAULT gen_random_uuid(),
+    name           varchar(255) NOT NULL,
+    redirect_uri   varchar(255) NOT NULL,
+    client_secret  varchar(255) NULL,
+    auth_method    varchar(255) NOT NULL,
+    public_key     varchar(255) NULL,
+    tls_subject_dn varchar(255) NULL,
+    tls_thumbprint varchar(255) NULL,
+    created_at     timestamptz  NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS mail_outbox
+(
+    uuid         uuid PRIMARY KEY      DEFAULT gen_random_uuid(),
+    recipient    varchar(255) NOT NULL,
+    subject      varchar(255) NOT NULL,
+    html         text         NOT NULL,
+    text         text         NOT NULL,
+    status       varchar(255) NOT NULL DEFAULT 'Pending',
+    attempts     integer      NOT NULL DEFAULT 0,
+    last_error   text         NULL,
+    next_attempt timestamptz  NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    sent_at      timestamptz  NULL,
+    created_at   timestamptz  NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS phone_verification_codes
+(
+    client       uuid PRIMARY KEY REFERENCES clients (sub),
+    phone_number varchar(255) NOT NULL,
+    code         varchar(255) NOT NULL,
+    attempts     integer      NOT NULL DEFAULT 0,
+    created_at   timestamptz  NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    expires_at   timestamptz  NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS recovery_codes
+(
+    uuid       uuid PRIMARY KEY,
+    client     uuid         NOT NULL REFERENCES clients (sub),
+    code       varchar(255) NOT NULL,
+    created_at timestamptz  NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE IF NOT EXISTS webauthn_credentials
+(
+    uuid          uuid PRIMARY KEY      DEFAULT gen_random_uuid(),
+    client        uuid         NOT NULL REFERENCES clients (sub),
+    name          varchar(255) NOT NULL,
+    credential_id text         NOT NULL UNIQUE,
+    public_key    text         NOT NULL,
+    algorithm     bigint       NOT NULL,
+    sign_count    bigint       NOT NULL DEFAULT 0,
+    transports    varchar(255) NOT NULL DEFAULT '',
+    created_at    timestamptz  NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    last_used     timestamptz  NULL
+);
+
+CREATE TABLE IF NOT EXISTS login_attempts
+(
+    key          varchar(255) PRIMARY KEY,
+    failures     integer      NOT NULL DEFAULT 0,
+    last_failure timestamptz  NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    locked_until timestamptz  NULL
+);
+
+CREATE TABLE IF NOT EXISTS signing_keys
+(
+    pid         varchar(255) PRIMARY KEY,
+    private_key text         NOT NULL,
+    state       varchar(255) NOT NULL,
+    retired_at  timestamptz  NULL,
+    created_at  timestamptz  NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
@@ -23,6 +23,8 @@
  *  SOFTWARE.
  */
 
+use crate::openid::verification::PasswordFeedback;
+use axum::http::header::RETRY_AFTER;
 use axum::http::StatusCode;
 use axum::response::{IntoResponse, Response};
 use axum::Json;
@@ -33,18 +35,79 @@ pub enum ResponseError {
     Unauthorized,
     #[error("{0}")]
     BadRequest(String),
+    #[error("Too many requests")]
+    TooManyRequests,
+    #[error("{0}")]
+    Conflict(String),
+    #[error("{0}")]
+    NotFound(String),
+    #[error("{0}")]
+    Internal(String),
+    #[error("invalid_client")]
+    InvalidClient,
+    #[error("unsupported_grant_type")]
+    UnsupportedGrantType,
+    #[error("invalid_dpop_proof")]
+    InvalidDpopProof,
+    /// the password does not meet the policy
+    #[error("Password not strong enough")]
+    WeakPassword(PasswordFeedback),
+    /// too many failed logins, retry after the given seconds
+    #[error("Login locked")]
+    LoginLocked(i64),
 }
 
 impl IntoResponse for ResponseError {
     fn into_response(self) -> Response {
         match self {
+            ResponseError::LoginLocked(seconds) => {
+                return (
+                    StatusCode::TOO_MANY_REQUESTS,
+                    [(RETRY_AFTER, seconds.to_string())],
+                    Json(json!({"error": "login_locked", "retry_after": seconds})),
+                )
+                    .into_response()
+            }
             ResponseError::Unauthorized => (
                 StatusCode::UNAUTHORIZED,
                 Json(json!({"error": "Unauthorized"})),
             ),
+            ResponseError::WeakPassword(feedback) => {
+                (StatusCode::BAD_REQUEST, Json(json!(feedback)))
+            }
             ResponseError::BadRequest(error) => {
                 (StatusCode::BAD_REQUEST, Json(json!({ "error": error })))
             }
+            ResponseError::TooManyRequests => (
+                StatusCode::TOO_MANY_REQUESTS,
+                Json(json!({"error": "Too many requests"})),
+            ),
+            ResponseError::Conflict(error) => {
+                (StatusCode::CONFLICT, Json(json!({ "error": error })))
+            }
+            ResponseError::NotFound(error) => {
+                (StatusCode::NOT_FOUND, Json(json!({ "error": error })))
+            }
+            ResponseError::Internal(error) => {
+                // only log the details
+                error!("{}", error);
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(json!({"error": "Internal error"})),
+                )
+            }
+            ResponseError::InvalidClient => (
+                StatusCode::UNAUTHORIZED,
+                Json(json!({"error": "invalid_client"})),
+            ),
+            ResponseError::UnsupportedGrantType => (
+                StatusCode::BAD_REQUEST,
+                Json(json!({"error": "unsupported_grant_type"})),
+            ),
+            ResponseError::InvalidDpopProof => (
+                StatusCode::BAD_REQUEST,
+                Json(json!({"error": "invalid_dpop_proof"})),
+            ),
         }
         .into_response()
     }
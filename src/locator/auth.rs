@@ -32,14 +32,17 @@ use std::collections::HashMap;
 pub struct Session {
     sub: Uuid,
     started: DateTime<Utc>,
+    /// the authentication methods used to start the session (RFC 8176)
+    amr: Vec<String>,
 }
 
 impl Session {
     /// Create a new session instance from the given sub
-    pub fn new(sub: Uuid) -> Self {
+    pub fn new(sub: Uuid, amr: Vec<String>) -> Self {
         Self {
             sub,
             started: Utc::now(),
+            amr,
         }
     }
 
@@ -67,12 +70,12 @@ impl AuthHandler {
         }
     }
 
-    /// Register a new session for the given sub
-    pub fn start_session(&mut self, sub: Uuid) -> String {
+    /// Register a new session for the given sub, authenticated with the given methods
+    pub fn start_session(&mut self, sub: Uuid, amr: Vec<String>) -> String {
         // generate the sessionID
         let session_id = Self::create_session_id();
         // generate the sessions
-        let session = Session::new(sub);
+        let session = Session::new(sub, amr);
 
         // save the session into the HashMap
         self.sessions.insert(session_id.clone(), session);
@@ -101,6 +104,11 @@ impl AuthHandler {
         self.sessions.remove(session_id);
     }
 
+    /// End all sessions of the given sub
+    pub fn end_sessions(&mut self, sub: &Uuid) {
+        self.sessions.retain(|_, session| session.sub() != sub);
+    }
+
     /// Generate a new random sessionID
     fn create_session_id() -> String {
         // generate random bytes
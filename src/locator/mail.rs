@@ -23,54 +23,378 @@
  *  SOFTWARE.
  */
 
-use lettre::message::header::ContentType;
+use crate::locator::template::RenderedMail;
+use lettre::message::dkim::{DkimConfig, DkimSigningAlgorithm, DkimSigningKey};
+use lettre::message::{Mailbox, MultiPart};
 use lettre::transport::smtp::authentication::Credentials;
-use lettre::transport::smtp::response::Response;
-use lettre::transport::smtp::Error;
 use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use rbatis::Uuid;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
 
-pub struct MailSender {
-    username: String,
-    password: String,
-    host: String,
+#[derive(Error, Debug)]
+pub enum MailError {
+    #[error("Invalid configuration: {0}")]
+    Configuration(String),
+    #[error("{0}")]
+    Address(#[from] lettre::address::AddressError),
+    #[error("{0}")]
+    Message(#[from] lettre::error::Error),
+    #[error("{0}")]
+    Smtp(#[from] lettre::transport::smtp::Error),
+    #[error("{0}")]
+    Io(#[from] std::io::Error),
 }
 
-#[derive(TypedBuilder)]
+#[derive(TypedBuilder, Getters, Clone, Debug)]
+#[get = "pub"]
 pub struct MailOptions {
     to: String,
     subject: String,
-    content: String,
+    html: String,
+    text: String,
+}
+
+impl MailOptions {
+    /// Build the options from a rendered template
+    pub fn from_template(to: String, mail: RenderedMail) -> Self {
+        Self {
+            to,
+            subject: mail.subject().clone(),
+            html: mail.html().clone(),
+            text: mail.text().clone(),
+        }
+    }
+}
+
+/// Delivers the built messages
+#[async_trait]
+pub trait MailTransport: Send + Sync {
+    /// Deliver the message, the options are the unencoded content of it
+    async fn send(&self, message: Message, options: &MailOptions) -> Result<(), MailError>;
+}
+
+/// How the connection to the smtp server is secured
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SmtpSecurity {
+    /// implicit tls (default port 465)
+    Tls,
+    /// upgrade the plain connection (default port 587)
+    StartTls,
+    /// no encryption at all, only for local relays
+    None,
+}
+
+/// Relay the messages to a smtp server
+pub struct SmtpBackend {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+}
+
+impl SmtpBackend {
+    pub fn new(
+        host: &str,
+        port: Option<u16>,
+        security: SmtpSecurity,
+        credentials: Option<Credentials>,
+    ) -> Result<Self, MailError> {
+        let mut builder = match security {
+            SmtpSecurity::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(host)?,
+            SmtpSecurity::StartTls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)?,
+            SmtpSecurity::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host),
+        };
+        if let Some(port) = port {
+            builder = builder.port(port);
+        }
+        if let Some(credentials) = credentials {
+            builder = builder.credentials(credentials);
+        }
+
+        Ok(Self {
+            transport: builder.build(),
+        })
+    }
+}
+
+#[async_trait]
+impl MailTransport for SmtpBackend {
+    async fn send(&self, message: Message, _: &MailOptions) -> Result<(), MailError> {
+        self.transport.send(message).await?;
+        Ok(())
+    }
+}
+
+/// Write the messages as `.eml` files into a directory
+pub struct FileBackend {
+    directory: PathBuf,
+}
+
+impl FileBackend {
+    pub fn new(directory: impl Into<PathBuf>) -> Self {
+        Self {
+            directory: directory.into(),
+        }
+    }
+}
+
+#[async_trait]
+impl MailTransport for FileBackend {
+    async fn send(&self, message: Message, _: &MailOptions) -> Result<(), MailError> {
+        tokio::fs::create_dir_all(&self.directory).await?;
+        let path = self.directory.join(format!("{}.eml", Uuid::new()));
+        tokio::fs::write(path, message.formatted()).await?;
+
+        Ok(())
+    }
+}
+
+/// Log the messages instead of sending them (development only)
+pub struct StdoutBackend;
+
+#[async_trait]
+impl MailTransport for StdoutBackend {
+    async fn send(&self, _: Message, options: &MailOptions) -> Result<(), MailError> {
+        info!(
+            "Mail to {} - {}\n{}",
+            options.to(),
+            options.subject(),
+            options.text()
+        );
+        Ok(())
+    }
+}
+
+/// Keep the messages in memory, so they can be inspected (tests only)
+#[derive(Clone, Default)]
+pub struct MemoryBackend {
+    mails: Arc<Mutex<Vec<MailOptions>>>,
+}
+
+impl MemoryBackend {
+    /// All captured mails
+    pub fn mails(&self) -> Vec<MailOptions> {
+        self.mails.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl MailTransport for MemoryBackend {
+    async fn send(&self, _: Message, options: &MailOptions) -> Result<(), MailError> {
+        self.mails.lock().unwrap().push(options.clone());
+        Ok(())
+    }
+}
+
+pub struct MailSender {
+    from: Mailbox,
+    transport: Box<dyn MailTransport>,
+    /// sign the messages if configured
+    dkim: Option<DkimConfig>,
 }
 
 impl MailSender {
-    /// Create new MailSender
-    /// This reads all necessary data from the env file
-    pub fn new() -> Self {
+    /// Create new MailSender with the transport of `MAIL_TRANSPORT` (smtp, file, stdout or memory)
+    pub fn new() -> Result<Self, MailError> {
+        let transport: Box<dyn MailTransport> = match std::env::var("MAIL_TRANSPORT")
+            .unwrap_or_else(|_| "smtp".to_string())
+            .as_str()
+        {
+            "smtp" => {
+                let host = std::env::var("SMTP_HOST")
+                    .map_err(|_| MailError::Configuration("SMTP_HOST is not set".into()))?;
+                let port = match std::env::var("SMTP_PORT") {
+                    Ok(port) => Some(port.parse().map_err(|_| {
+                        MailError::Configuration("SMTP_PORT is not a valid port".into())
+                    })?),
+                    Err(_) => None,
+                };
+                let security = match std::env::var("SMTP_SECURITY")
+                    .unwrap_or_else(|_| "tls".to_string())
+                    .as_str()
+                {
+                    "tls" => SmtpSecurity::Tls,
+                    "starttls" => SmtpSecurity::StartTls,
+                    "none" => SmtpSecurity::None,
+                    security => {
+                        return Err(MailError::Configuration(format!(
+                            "Unknown SMTP_SECURITY {}",
+                            security
+                        )))
+                    }
+                };
+                // the credentials are optional for local relays
+                let credentials = match (std::env::var("SMTP_USER"), std::env::var("SMTP_PASSWORD"))
+                {
+                    (Ok(username), Ok(password)) => Some(Credentials::new(username, password)),
+                    _ => None,
+                };
+
+                Box::new(SmtpBackend::new(
+                    host.as_str(),
+                    port,
+                    security,
+                    credentials,
+                )?)
+            }
+            "file" => Box::new(FileBackend::new(
+                std::env::var("MAIL_DIRECTORY").unwrap_or_else(|_| "mails".to_string()),
+            )),
+            "stdout" => Box::new(StdoutBackend),
+            "memory" => Box::new(MemoryBackend::default()),
+            transport => {
+                return Err(MailError::Configuration(format!(
+                    "Unknown MAIL_TRANSPORT {}",
+                    transport
+                )))
+            }
+        };
+
+        // the sender defaults to the smtp user
+        let address = std::env::var("MAIL_FROM")
+            .or_else(|_| std::env::var("SMTP_USER"))
+            .map_err(|_| MailError::Configuration("MAIL_FROM is not set".into()))?;
+        let from = Mailbox::new(std::env::var("MAIL_FROM_NAME").ok(), address.parse()?);
+
+        let mut sender = Self::with_transport(from, transport);
+        // dkim is enabled with the selector
+        if let Ok(selector) = std::env::var("DKIM_SELECTOR") {
+            let domain = std::env::var("DKIM_DOMAIN")
+                .unwrap_or_else(|_| sender.from.email.domain().to_string());
+            let path = std::env::var("DKIM_PRIVATE_KEY_PATH")
+                .map_err(|_| MailError::Configuration("DKIM_PRIVATE_KEY_PATH is not set".into()))?;
+            let algorithm = match std::env::var("DKIM_ALGORITHM")
+                .unwrap_or_else(|_| "rsa".to_string())
+                .as_str()
+            {
+                "rsa" => DkimSigningAlgorithm::Rsa,
+                "ed25519" => DkimSigningAlgorithm::Ed25519,
+                algorithm => {
+                    return Err(MailError::Configuration(format!(
+                        "Unknown DKIM_ALGORITHM {}",
+                        algorithm
+                    )))
+                }
+            };
+
+            let key = std::fs::read_to_string(path)?;
+            sender = sender.with_dkim(selector, domain, key.as_str(), algorithm)?;
+        }
+
+        Ok(sender)
+    }
+
+    /// Create new MailSender with the given transport
+    pub fn with_transport(from: Mailbox, transport: Box<dyn MailTransport>) -> Self {
         Self {
-            username: std::env::var("SMTP_USER").unwrap(),
-            password: std::env::var("SMTP_PASSWORD").unwrap(),
-            host: std::env::var("SMTP_HOST").unwrap(),
+            from,
+            transport,
+            dkim: None,
         }
     }
 
+    /// Sign the messages with the given key (pkcs1 pem for rsa, base64 for ed25519)
+    pub fn with_dkim(
+        mut self,
+        selector: String,
+        domain: String,
+        key: &str,
+        algorithm: DkimSigningAlgorithm,
+    ) -> Result<Self, MailError> {
+        let key = DkimSigningKey::new(key, algorithm).map_err(|error| {
+            MailError::Configuration(format!("Invalid DKIM private key: {}", error))
+        })?;
+        self.dkim = Some(DkimConfig::default_config(selector, domain, key));
+
+        Ok(self)
+    }
+
     /// Sends an email with the given data
-    pub async fn send(&self, options: MailOptions) -> Result<Response, Error> {
+    pub async fn send(&self, options: MailOptions) -> Result<(), MailError> {
         // build the message
-        let message = Message::builder()
-            .from(self.username.parse().unwrap())
-            .to(options.to.parse().unwrap())
-            .subject(options.subject)
-            .header(ContentType::TEXT_HTML)
-            .body(options.content)
-            .unwrap();
+        let mut message = Message::builder()
+            .from(self.from.clone())
+            .to(options.to.parse()?)
+            .subject(options.subject.clone())
+            .multipart(MultiPart::alternative_plain_html(
+                options.text.clone(),
+                options.html.clone(),
+            ))?;
+        if let Some(dkim) = &self.dkim {
+            message.sign(dkim);
+        }
+
+        self.transport.send(message, &options).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options() -> MailOptions {
+        MailOptions::builder()
+            .to("test@example.com".to_string())
+            .subject("Subject".to_string())
+            .html("<p>Content</p>".to_string())
+            .text("Content".to_string())
+            .build()
+    }
+
+    #[tokio::test]
+    async fn test_memory() {
+        let backend = MemoryBackend::default();
+        let sender = MailSender::with_transport(
+            "server@example.com".parse().unwrap(),
+            Box::new(backend.clone()),
+        );
+
+        sender.send(options()).await.unwrap();
+        let mails = backend.mails();
+        assert_eq!(mails.len(), 1);
+        assert_eq!(mails[0].to(), "test@example.com");
+    }
+
+    #[tokio::test]
+    async fn test_file() {
+        let directory = std::env::temp_dir().join(format!("mails-{}", Uuid::new()));
+        let sender = MailSender::with_transport(
+            "Server <server@example.com>".parse().unwrap(),
+            Box::new(FileBackend::new(directory.clone())),
+        );
 
-        // connect
-        let transport = AsyncSmtpTransport::<Tokio1Executor>::relay(&self.host)
+        sender.send(options()).await.unwrap();
+        let files = std::fs::read_dir(&directory).unwrap().collect::<Vec<_>>();
+        assert_eq!(files.len(), 1);
+
+        // a complete message
+        let content = std::fs::read_to_string(files[0].as_ref().unwrap().path()).unwrap();
+        assert!(content.contains("To: test@example.com"));
+        assert!(content.contains("Subject: Subject"));
+        assert!(content.contains("From: Server <server@example.com>"));
+    }
+
+    #[tokio::test]
+    async fn test_dkim() {
+        let directory = std::env::temp_dir().join(format!("mails-{}", Uuid::new()));
+        let key = openssl::rsa::Rsa::generate(2048)
             .unwrap()
-            .credentials(Credentials::from((&self.username, &self.password)))
-            .build();
+            .private_key_to_pem()
+            .unwrap();
+        let sender = MailSender::with_transport(
+            "server@example.com".parse().unwrap(),
+            Box::new(FileBackend::new(directory.clone())),
+        )
+        .with_dkim(
+            "mail".to_string(),
+            "example.com".to_string(),
+            String::from_utf8(key).unwrap().as_str(),
+            DkimSigningAlgorithm::Rsa,
+        )
+        .unwrap();
 
-        // send the email
-        transport.send(message).await
+        sender.send(options()).await.unwrap();
+        let file = std::fs::read_dir(&directory).unwrap().next().unwrap();
+        let content = std::fs::read_to_string(file.unwrap().path()).unwrap();
+        assert!(content.contains("DKIM-Signature:"));
+        assert!(content.contains("s=mail"));
     }
 }
@@ -24,8 +24,11 @@
  */
 
 use crate::locator::auth::AuthHandler;
-use crate::locator::mail::MailSender;
 use crate::locator::paseto::TokenSigner;
+use crate::locator::replay::ReplayCache;
+use crate::locator::sms::SmsSender;
+use crate::locator::template::TemplateEngine;
+use crate::locator::webauthn::WebauthnHandler;
 use rbatis::rbatis::Rbatis;
 use std::sync::Arc;
 use tokio::sync::Mutex;
@@ -33,6 +36,10 @@ use tokio::sync::Mutex;
 pub mod auth;
 pub mod mail;
 pub mod paseto;
+pub mod replay;
+pub mod sms;
+pub mod template;
+pub mod webauthn;
 
 #[derive(Getters, MutGetters)]
 #[get = "pub"]
@@ -41,8 +48,13 @@ pub struct Locator {
     connection: Rbatis,
     // the paseto instance
     paseto: TokenSigner,
-    mail: MailSender,
+    templates: TemplateEngine,
+    sms: Arc<dyn SmsSender>,
     auth: AuthHandler,
+    replay: ReplayCache,
+    webauthn: WebauthnHandler,
+    /// the CAs used to verify the certificates for tls_client_auth
+    client_ca_path: Option<String>,
 }
 
 pub type LocatorPointer = Arc<Mutex<Locator>>;
@@ -51,16 +63,27 @@ impl Locator {
     pub async fn new() -> LocatorPointer {
         // establish the connection
         let connection = crate::database::establish_connection().await;
-        // create new instance of the signer
-        let paseto = TokenSigner::new();
-        let mail = MailSender::new();
+        // load the signing keys
+        let paseto = TokenSigner::load(&connection)
+            .await
+            .unwrap_or_else(|error| panic!("Failed to load the signing keys: {}", error));
+        let templates = TemplateEngine::new();
+        let sms = sms::from_env()
+            .unwrap_or_else(|error| panic!("Failed to setup the sms transport: {}", error));
         let auth = AuthHandler::new();
+        let replay = ReplayCache::new();
+        let webauthn = WebauthnHandler::new();
+        let client_ca_path = crate::TLS_CLIENT_CA_PATH.clone();
 
         Arc::new(Mutex::new(Self {
             connection,
             paseto,
-            mail,
+            templates,
+            sms,
             auth,
+            replay,
+            webauthn,
+            client_ca_path,
         }))
     }
 }
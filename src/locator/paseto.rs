@@ -23,67 +23,561 @@
  *  SOFTWARE.
  */
 
-use chrono::{Duration, Utc};
-use openssl::pkey::PKey;
-use rbatis::Uuid;
+use crate::database::key::StoredSigningKey;
+use crate::ISSUER;
+use chrono::{DateTime, Duration, Utc};
+use openssl::pkey::{Id, PKey, Private};
+use openssl::symm::Cipher;
+use rbatis::crud::CRUD;
+use rbatis::rbatis::Rbatis;
+use rbatis::{TimestampZ, Uuid};
 use rusty_paseto::prelude::*;
 
-pub struct TokenSigner {
-    // for public
+/// The lifetime of the issued tokens (in minutes)
+pub const TOKEN_LIFETIME: i64 = 5;
+
+#[derive(Error, Debug)]
+pub enum KeyError {
+    #[error("No signing key found in {0}")]
+    Missing(String),
+    #[error("The signing key {0} is malformed")]
+    Malformed(String),
+    #[error("The signing key {0} could not be decrypted")]
+    Decryption(String),
+    #[error("Expected exactly one active signing key, found {0}")]
+    ActiveKeys(usize),
+    #[error("The key file {0} can not persist rotations")]
+    ReadOnly(String),
+    #[error("Invalid configuration: {0}")]
+    Configuration(String),
+    #[error("{0}")]
+    Io(#[from] std::io::Error),
+    #[error("{0}")]
+    Database(#[from] rbatis::Error),
+}
+
+/// The storage the keys are loaded from
+pub enum KeySource {
+    /// A single pem encoded private key at the given path
+    File(String),
+    /// Encrypted rows in the signing_keys table
+    Database([u8; 32]),
+}
+
+impl KeySource {
+    /// Read the source from the env vars
+    pub fn from_env() -> Result<Self, KeyError> {
+        match std::env::var("KEY_SOURCE")
+            .unwrap_or_else(|_| "file".to_string())
+            .as_str()
+        {
+            "file" => Ok(Self::File(
+                std::env::var("PRIVATE_KEY_PATH").unwrap_or_else(|_| "private_key.pem".to_string()),
+            )),
+            "database" => {
+                // decode the encryption key
+                let key = std::env::var("KEY_ENCRYPTION_KEY")
+                    .map_err(|_| KeyError::Configuration("KEY_ENCRYPTION_KEY is not set".into()))?;
+                let key = base64::decode(key).map_err(|_| {
+                    KeyError::Configuration("KEY_ENCRYPTION_KEY is not valid base64".into())
+                })?;
+
+                Ok(Self::Database(key.try_into().map_err(|_| {
+                    KeyError::Configuration("KEY_ENCRYPTION_KEY has to be 32 bytes long".into())
+                })?))
+            }
+            source => Err(KeyError::Configuration(format!(
+                "Unknown KEY_SOURCE {}",
+                source
+            ))),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub enum KeyState {
+    /// Published for verifiers, but not used for signing yet
+    Pending,
+    /// The key used for signing new tokens
+    Active,
+    /// Only used for verifying tokens which were signed before the rotation
+    Retired,
+}
+
+#[derive(Getters)]
+#[get = "pub"]
+pub struct SigningKey {
+    /// The PASERK pid of the public key
+    pid: String,
+    state: KeyState,
+    /// The moment the key got retired
+    retired_at: Option<DateTime<Utc>>,
+    #[getset(skip)]
     private_key: Key<64>,
+    #[getset(skip)]
     public_key: Key<32>,
 }
 
-impl TokenSigner {
-    /// Init a new instance of the TokenSigner
-    pub fn new() -> Self {
+impl SigningKey {
+    /// Build a new key from the given ed25519 key pair
+    pub fn from_pkey(pkey: &PKey<Private>, state: KeyState) -> Self {
         // This whole section could be much cleaner, but the 32byte key has to be transformed
         // into the 64byte ec signature key manually
-
-        // load the keys
-        let private_key_raw = PKey::private_key_from_pem(include_bytes!("../../private_key.pem"))
-            .unwrap()
-            // convert to raw
-            .raw_private_key()
-            .unwrap();
-        let public_key_raw = PKey::public_key_from_pem(include_bytes!("../../public_key.pem"))
-            .unwrap()
-            // convert to raw
-            .raw_public_key()
-            .unwrap();
+        let private_key_raw = pkey.raw_private_key().unwrap();
+        let public_key_raw = pkey.raw_public_key().unwrap();
 
         // build the signature key
         let mut bytes: [u8; 64] = [0u8; 64];
         bytes[..32].copy_from_slice(private_key_raw.as_slice());
         bytes[32..].copy_from_slice(public_key_raw.as_slice());
 
-        // setup the keys
-        let public_key = Key::<32>::from(public_key_raw.as_slice());
-        let private_key = Key::<64>::from(bytes);
-
-        // construct
         Self {
-            public_key,
-            private_key,
+            pid: Self::calculate_pid(public_key_raw.as_slice()),
+            state,
+            retired_at: None,
+            private_key: Key::<64>::from(bytes),
+            public_key: Key::<32>::from(public_key_raw.as_slice()),
+        }
+    }
+
+    /// Generate a new random pending key
+    pub fn generate() -> Self {
+        let pkey = PKey::generate_ed25519().unwrap();
+        Self::from_pkey(&pkey, KeyState::Pending)
+    }
+
+    /// The public key serialized as PASERK (k4.public)
+    pub fn paserk(&self) -> String {
+        format!(
+            "k4.public.{}",
+            base64::encode_config(self.public_key.as_slice(), base64::URL_SAFE_NO_PAD)
+        )
+    }
+
+    /// Checks if tokens signed with this key are still accepted
+    pub fn verifies(&self) -> bool {
+        match self.retired_at {
+            // retired keys verify until all their tokens are expired
+            Some(retired_at) => retired_at + Duration::minutes(TOKEN_LIFETIME) >= Utc::now(),
+            None => true,
+        }
+    }
+
+    /// Calculate the PASERK pid of the given raw public key
+    /// https://github.com/paseto-standard/paserk/blob/master/operations/ID.md
+    pub fn calculate_pid(public_key: &[u8]) -> String {
+        use blake2::digest::{Update, VariableOutput};
+
+        let header = "k4.pid.";
+        let paserk = format!(
+            "k4.public.{}",
+            base64::encode_config(public_key, base64::URL_SAFE_NO_PAD)
+        );
+
+        // hash with blake2b-264
+        let mut hasher = blake2::Blake2bVar::new(33).unwrap();
+        hasher.update(header.as_bytes());
+        hasher.update(paserk.as_bytes());
+        let mut hash = [0u8; 33];
+        hasher.finalize_variable(&mut hash).unwrap();
+
+        format!(
+            "{}{}",
+            header,
+            base64::encode_config(hash, base64::URL_SAFE_NO_PAD)
+        )
+    }
+}
+
+pub struct TokenSigner {
+    keys: Vec<SigningKey>,
+    source: KeySource,
+}
+
+impl TokenSigner {
+    /// Load the keys from the source configured in the env vars.
+    /// Missing keys are only generated if GENERATE_KEYS is enabled.
+    pub async fn load(connection: &Rbatis) -> Result<Self, KeyError> {
+        let generate = std::env::var("GENERATE_KEYS")
+            .map(|value| value == "true")
+            .unwrap_or(false);
+
+        let signer = match KeySource::from_env()? {
+            KeySource::File(path) => Self::load_file(path, generate)?,
+            KeySource::Database(key) => Self::load_database(key, connection, generate).await?,
+        };
+
+        // there has to be exactly one signing key
+        let active = signer
+            .keys
+            .iter()
+            .filter(|key| key.state == KeyState::Active)
+            .count();
+        if active != 1 {
+            return Err(KeyError::ActiveKeys(active));
+        }
+        Ok(signer)
+    }
+
+    /// Load the pem encoded private key from the given path
+    fn load_file(path: String, generate: bool) -> Result<Self, KeyError> {
+        let pkey = match std::fs::read(path.as_str()) {
+            Ok(pem) => PKey::private_key_from_pem(pem.as_slice())
+                .map_err(|_| KeyError::Malformed(path.clone()))?,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+                if !generate {
+                    return Err(KeyError::Missing(path));
+                }
+
+                // generate and persist a new key
+                info!("Generating a new signing key at {}", path);
+                let pkey = PKey::generate_ed25519().unwrap();
+                std::fs::write(path.as_str(), pkey.private_key_to_pem_pkcs8().unwrap())?;
+                pkey
+            }
+            Err(error) => return Err(error.into()),
+        };
+
+        // only ed25519 keys can be used for v4.public
+        if pkey.id() != Id::ED25519 {
+            return Err(KeyError::Malformed(path));
+        }
+
+        Ok(Self {
+            keys: vec![SigningKey::from_pkey(&pkey, KeyState::Active)],
+            source: KeySource::File(path),
+        })
+    }
+
+    /// Load and decrypt all stored keys from the database
+    async fn load_database(
+        encryption_key: [u8; 32],
+        connection: &Rbatis,
+        generate: bool,
+    ) -> Result<Self, KeyError> {
+        let stored = connection.fetch_list::<StoredSigningKey>().await?;
+
+        let mut signer = Self {
+            keys: Vec::new(),
+            source: KeySource::Database(encryption_key),
+        };
+        if stored.is_empty() {
+            if !generate {
+                return Err(KeyError::Missing("the database".into()));
+            }
+
+            // generate and persist a new key
+            info!("Generating a new signing key in the database");
+            let mut key = SigningKey::generate();
+            key.state = KeyState::Active;
+            signer.keys.push(key);
+            signer.save(connection).await?;
+
+            return Ok(signer);
+        }
+
+        for stored in stored {
+            // decrypt the raw private key
+            let raw = Self::decrypt(&encryption_key, &stored)?;
+            let pkey = PKey::private_key_from_raw_bytes(raw.as_slice(), Id::ED25519)
+                .map_err(|_| KeyError::Malformed(stored.pid().clone()))?;
+
+            let mut key = SigningKey::from_pkey(&pkey, stored.state().clone());
+            // the stored pid has to match the key
+            if &key.pid != stored.pid() {
+                return Err(KeyError::Malformed(stored.pid().clone()));
+            }
+            key.retired_at = stored
+                .retired_at()
+                .as_ref()
+                .map(|timestamp| timestamp.inner);
+            signer.keys.push(key);
+        }
+        // drop the retired keys which are not needed anymore
+        signer.prune();
+
+        Ok(signer)
+    }
+
+    /// Checks if rotations of the key set can be persisted
+    pub fn persistent(&self) -> bool {
+        matches!(self.source, KeySource::Database(_))
+    }
+
+    /// Persist the current key set, only possible if the keys are stored in the database
+    pub async fn save(&self, connection: &Rbatis) -> Result<(), KeyError> {
+        let encryption_key = match &self.source {
+            KeySource::Database(key) => key,
+            // the file only holds a single key
+            KeySource::File(path) => return Err(KeyError::ReadOnly(path.clone())),
+        };
+
+        // remove the pruned keys
+        for stored in connection.fetch_list::<StoredSigningKey>().await? {
+            if !self.keys.iter().any(|key| &key.pid == stored.pid()) {
+                connection
+                    .remove_by_column::<StoredSigningKey, _>("pid", stored.pid())
+                    .await?;
+            }
         }
+
+        for key in self.keys.iter() {
+            // encrypt the raw private key
+            let stored = StoredSigningKey::builder()
+                .pid(key.pid.clone())
+                .private_key(Self::encrypt(encryption_key, key))
+                .state(key.state.clone())
+                .retired_at(key.retired_at.map(TimestampZ::from))
+                .build();
+
+            // insert or update
+            let exists: Option<StoredSigningKey> =
+                connection.fetch_by_column("pid", &key.pid).await?;
+            if exists.is_some() {
+                connection.update_by_column("pid", &stored).await?;
+            } else {
+                connection.save(&stored, &[]).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Encrypt the raw private key with aes-256-gcm, the pid is used as aad
+    fn encrypt(encryption_key: &[u8; 32], key: &SigningKey) -> String {
+        let mut nonce = [0u8; 12];
+        openssl::rand::rand_bytes(&mut nonce).unwrap();
+        let mut tag = [0u8; 16];
+
+        let ciphertext = openssl::symm::encrypt_aead(
+            Cipher::aes_256_gcm(),
+            encryption_key,
+            Some(&nonce),
+            key.pid.as_bytes(),
+            &key.private_key.as_slice()[..32],
+            &mut tag,
+        )
+        .unwrap();
+
+        // nonce | tag | ciphertext
+        base64::encode([nonce.as_slice(), tag.as_slice(), ciphertext.as_slice()].concat())
     }
 
-    /// Sign a new PASETO-Token with the given sub for use over openid
-    pub fn sign(&self, sub: &Uuid) -> String {
+    /// Decrypt the raw private key of the stored key
+    fn decrypt(encryption_key: &[u8; 32], stored: &StoredSigningKey) -> Result<Vec<u8>, KeyError> {
+        let error = || KeyError::Decryption(stored.pid().clone());
+
+        let data = base64::decode(stored.private_key()).map_err(|_| error())?;
+        if data.len() < 28 {
+            return Err(error());
+        }
+
+        openssl::symm::decrypt_aead(
+            Cipher::aes_256_gcm(),
+            encryption_key,
+            Some(&data[..12]),
+            stored.pid().as_bytes(),
+            &data[28..],
+            &data[12..28],
+        )
+        .map_err(|_| error())
+    }
+
+    /// Get all keys which are still able to verify tokens
+    pub fn keys(&self) -> Vec<&SigningKey> {
+        self.keys.iter().filter(|key| key.verifies()).collect()
+    }
+
+    /// Get the key currently used for signing
+    pub fn active(&self) -> &SigningKey {
+        self.keys
+            .iter()
+            .find(|key| key.state == KeyState::Active)
+            .unwrap()
+    }
+
+    /// Generate a new pending key and return its pid
+    pub fn generate(&mut self) -> String {
+        let key = SigningKey::generate();
+        let pid = key.pid.clone();
+
+        self.keys.push(key);
+        pid
+    }
+
+    /// Promote the pending key with the given pid to the active one and retire the current.
+    /// Returns false if there is no pending key with this pid.
+    pub fn promote(&mut self, pid: &str) -> bool {
+        // prevent promoting unknown or already used keys
+        if !self
+            .keys
+            .iter()
+            .any(|key| key.pid == pid && key.state == KeyState::Pending)
+        {
+            return false;
+        }
+
+        for key in self.keys.iter_mut() {
+            if key.pid == pid {
+                key.state = KeyState::Active;
+            } else if key.state == KeyState::Active {
+                key.state = KeyState::Retired;
+                key.retired_at = Some(Utc::now());
+            }
+        }
+        self.prune();
+
+        true
+    }
+
+    /// Remove all retired keys whose tokens have expired
+    pub fn prune(&mut self) {
+        self.keys.retain(|key| key.verifies());
+    }
+
+    /// Sign a new PASETO-Token with the given sub for use over openid.
+    /// The entries of the claims object are added as custom claims.
+    pub fn sign(&self, sub: &Uuid, audience: &str, claims: &serde_json::Value) -> String {
+        let key = self.active();
         // build private key
         let private_key =
-            PasetoAsymmetricPrivateKey::<V4, Public>::from(self.private_key.as_slice());
+            PasetoAsymmetricPrivateKey::<V4, Public>::from(key.private_key.as_slice());
         // build expiry
-        let expiry = Utc::now() + Duration::minutes(5);
+        let expiry = Utc::now() + Duration::minutes(TOKEN_LIFETIME);
         // convert sub
         let sub = sub.to_string();
+        // reference the signing key in the footer
+        let footer = json!({ "kid": key.pid }).to_string();
 
-        // sign the token
-        let result = PasetoBuilder::<V4, Public>::default()
+        let mut builder = PasetoBuilder::<V4, Public>::default();
+        builder
             .set_claim(SubjectClaim::from(sub.as_str()))
+            .set_claim(IssuerClaim::from(ISSUER.as_str()))
+            .set_claim(AudienceClaim::from(audience))
             .set_claim(ExpirationClaim::try_from(expiry.to_rfc3339()).unwrap())
-            .build(&private_key)
-            .unwrap();
-        result
+            .set_footer(Footer::from(footer.as_str()));
+        // add the custom claims
+        if let Some(claims) = claims.as_object() {
+            for (name, value) in claims {
+                builder.set_claim(CustomClaim::try_from((name.as_str(), value.clone())).unwrap());
+            }
+        }
+
+        // sign the token
+        builder.build(&private_key).unwrap()
+    }
+
+    /// Verify the given token with the key referenced in its footer and return the claims
+    pub fn verify(&self, token: &str) -> Option<serde_json::Value> {
+        // extract the footer (v4.public.payload.footer)
+        let footer = token.splitn(4, '.').nth(3)?;
+        let footer = base64::decode_config(footer, base64::URL_SAFE_NO_PAD).ok()?;
+        let footer = String::from_utf8(footer).ok()?;
+
+        // get the referenced key
+        let kid = serde_json::from_str::<serde_json::Value>(footer.as_str())
+            .ok()?
+            .get("kid")?
+            .as_str()?
+            .to_string();
+        let key = self
+            .keys
+            .iter()
+            .find(|key| key.pid == kid && key.verifies())?;
+
+        // verify
+        let public_key = PasetoAsymmetricPublicKey::<V4, Public>::from(&key.public_key);
+        PasetoParser::<V4, Public>::default()
+            .set_footer(Footer::from(footer.as_str()))
+            .parse(token, &public_key)
+            .ok()
+    }
+}
+
+#[cfg(test)]
+impl TokenSigner {
+    /// Build a signer with a random active key which persists into the database
+    pub fn in_database(encryption_key: [u8; 32]) -> Self {
+        let mut key = SigningKey::generate();
+        key.state = KeyState::Active;
+
+        Self {
+            keys: vec![key],
+            source: KeySource::Database(encryption_key),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build a signer with a random active key
+    fn signer() -> TokenSigner {
+        let pkey = PKey::generate_ed25519().unwrap();
+
+        TokenSigner {
+            keys: vec![SigningKey::from_pkey(&pkey, KeyState::Active)],
+            source: KeySource::File("private_key.pem".into()),
+        }
+    }
+
+    #[test]
+    fn test_sign_and_verify() {
+        let signer = signer();
+        let sub = Uuid::new();
+
+        let token = signer.sign(&sub, "audience", &json!({ "scope": "openid" }));
+        let claims = signer.verify(token.as_str()).unwrap();
+        assert_eq!(claims["sub"], sub.to_string());
+        assert_eq!(claims["aud"], "audience");
+        assert_eq!(claims["scope"], "openid");
+    }
+
+    #[test]
+    fn test_rotation() {
+        let mut signer = signer();
+        let old = signer.active().pid().clone();
+        let token = signer.sign(&Uuid::new(), "audience", &json!({}));
+
+        // generate and promote a new key
+        let pid = signer.generate();
+        assert!(signer.promote(pid.as_str()));
+        assert_eq!(signer.active().pid(), &pid);
+        // can not promote twice
+        assert!(!signer.promote(pid.as_str()));
+
+        // the old token is still valid
+        assert!(signer.verify(token.as_str()).is_some());
+        assert!(signer
+            .keys()
+            .iter()
+            .any(|key| key.pid() == &old && key.state() == &KeyState::Retired));
+
+        // new tokens reference the new key
+        let token = signer.sign(&Uuid::new(), "audience", &json!({}));
+        assert!(token.ends_with(
+            base64::encode_config(json!({ "kid": pid }).to_string(), base64::URL_SAFE_NO_PAD)
+                .as_str()
+        ));
+        assert!(signer.verify(token.as_str()).is_some());
+    }
+
+    #[test]
+    fn test_encryption() {
+        let key = SigningKey::generate();
+        let encryption_key = [7u8; 32];
+
+        let stored = StoredSigningKey::builder()
+            .pid(key.pid.clone())
+            .private_key(TokenSigner::encrypt(&encryption_key, &key))
+            .state(KeyState::Pending)
+            .retired_at(None)
+            .build();
+        let raw = TokenSigner::decrypt(&encryption_key, &stored).unwrap();
+        assert_eq!(raw.as_slice(), &key.private_key.as_slice()[..32]);
+
+        // fails with the wrong key
+        assert!(TokenSigner::decrypt(&[0u8; 32], &stored).is_err());
     }
 }
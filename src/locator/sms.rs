@@ -0,0 +1,139 @@
+/*
+ * The MIT License (MIT)
+ *
+ * Copyright (c) 2022 Randoooom
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in the
+ * Software without restriction, including without limitation the rights to use, copy,
+ * modify, merge, publish, distribute, sublicense, and/or sell copies of the Software,
+ * and to permit persons to whom the Software is furnished to do so, subject to the
+ * following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+ * EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+ * MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+ * NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT
+ * HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR
+ * IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ *  SOFTWARE.
+ */
+
+use std::sync::{Arc, Mutex};
+
+#[derive(Error, Debug)]
+pub enum SmsError {
+    #[error("Invalid configuration: {0}")]
+    Configuration(String),
+    #[error("{0}")]
+    Http(#[from] reqwest::Error),
+    #[error("The provider responded with {0}")]
+    Status(u16),
+}
+
+/// Delivers text messages to phone numbers
+#[async_trait]
+pub trait SmsSender: Send + Sync {
+    async fn send(&self, to: &str, message: &str) -> Result<(), SmsError>;
+}
+
+/// Read the sender from `SMS_TRANSPORT` (console, memory or webhook)
+pub fn from_env() -> Result<Arc<dyn SmsSender>, SmsError> {
+    match std::env::var("SMS_TRANSPORT")
+        .unwrap_or_else(|_| "console".to_string())
+        .as_str()
+    {
+        "console" => Ok(Arc::new(ConsoleSms)),
+        "memory" => Ok(Arc::new(MemorySms::default())),
+        "webhook" => {
+            let url = std::env::var("SMS_WEBHOOK_URL")
+                .map_err(|_| SmsError::Configuration("SMS_WEBHOOK_URL is not set".into()))?;
+
+            Ok(Arc::new(WebhookSms::new(
+                url,
+                std::env::var("SMS_WEBHOOK_TOKEN").ok(),
+            )))
+        }
+        transport => Err(SmsError::Configuration(format!(
+            "Unknown SMS_TRANSPORT {}",
+            transport
+        ))),
+    }
+}
+
+/// Log the messages instead of sending them (development only)
+pub struct ConsoleSms;
+
+#[async_trait]
+impl SmsSender for ConsoleSms {
+    async fn send(&self, to: &str, message: &str) -> Result<(), SmsError> {
+        info!("SMS to {}: {}", to, message);
+        Ok(())
+    }
+}
+
+/// Keep the messages in memory, so they can be inspected (tests only)
+#[derive(Clone, Default)]
+pub struct MemorySms {
+    messages: Arc<Mutex<Vec<(String, String)>>>,
+}
+
+impl MemorySms {
+    /// All captured messages as (to, message)
+    pub fn messages(&self) -> Vec<(String, String)> {
+        self.messages.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl SmsSender for MemorySms {
+    async fn send(&self, to: &str, message: &str) -> Result<(), SmsError> {
+        self.messages
+            .lock()
+            .unwrap()
+            .push((to.to_string(), message.to_string()));
+        Ok(())
+    }
+}
+
+/// Post the messages as json (`{"to": .., "message": ..}`) to the url of the provider
+pub struct WebhookSms {
+    url: String,
+    /// sent as bearer token if set
+    token: Option<String>,
+    client: reqwest::Client,
+}
+
+impl WebhookSms {
+    pub fn new(url: String, token: Option<String>) -> Self {
+        Self {
+            url,
+            token,
+            client: reqwest::Client::new(),
+        }
+    }
+}
+
+#[async_trait]
+impl SmsSender for WebhookSms {
+    async fn send(&self, to: &str, message: &str) -> Result<(), SmsError> {
+        let mut request = self
+            .client
+            .post(self.url.as_str())
+            .json(&json!({ "to": to, "message": message }));
+        if let Some(token) = &self.token {
+            request = request.bearer_auth(token);
+        }
+
+        let response = request.send().await?;
+        if !response.status().is_success() {
+            return Err(SmsError::Status(response.status().as_u16()));
+        }
+
+        Ok(())
+    }
+}
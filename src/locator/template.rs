@@ -0,0 +1,370 @@
+/*
+ * The MIT License (MIT)
+ *
+ * Copyright (c) 2022 Randoooom
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in the
+ * Software without restriction, including without limitation the rights to use, copy,
+ * modify, merge, publish, distribute, sublicense, and/or sell copies of the Software,
+ * and to permit persons to whom the Software is furnished to do so, subject to the
+ * following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+ * EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+ * MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+ * NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT
+ * HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR
+ * IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ *  SOFTWARE.
+ */
+
+use std::collections::HashMap;
+use std::path::Path;
+
+/// The locale used if no template exists for the locale of the client
+const FALLBACK_LOCALE: &str = "en";
+
+/// The outgoing mails
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MailTemplate {
+    EmailVerification,
+    PasswordReset,
+    /// Confirmation of a new email (sent to the new address)
+    EmailChange,
+    /// Security notice about a pending email change (sent to the old address)
+    EmailChangeNotice,
+    MagicLink,
+    /// Security notices about account events
+    NewLogin,
+    PasswordChanged,
+    TotpEnabled,
+    TotpDisabled,
+    EmailChanged,
+    AccountDeleted,
+    AuthenticatorAdded,
+    AuthenticatorRemoved,
+    /// Sent with an unlock link after too many failed logins
+    AccountLocked,
+}
+
+impl MailTemplate {
+    pub const ALL: [MailTemplate; 14] = [
+        MailTemplate::EmailVerification,
+        MailTemplate::PasswordReset,
+        MailTemplate::EmailChange,
+        MailTemplate::EmailChangeNotice,
+        MailTemplate::MagicLink,
+        MailTemplate::NewLogin,
+        MailTemplate::PasswordChanged,
+        MailTemplate::TotpEnabled,
+        MailTemplate::TotpDisabled,
+        MailTemplate::EmailChanged,
+        MailTemplate::AccountDeleted,
+        MailTemplate::AuthenticatorAdded,
+        MailTemplate::AuthenticatorRemoved,
+        MailTemplate::AccountLocked,
+    ];
+
+    /// The file name of the template (without extension)
+    pub fn name(&self) -> &'static str {
+        match self {
+            MailTemplate::EmailVerification => "email_verification",
+            MailTemplate::PasswordReset => "password_reset",
+            MailTemplate::EmailChange => "email_change",
+            MailTemplate::EmailChangeNotice => "email_change_notice",
+            MailTemplate::MagicLink => "magic_link",
+            MailTemplate::NewLogin => "new_login",
+            MailTemplate::PasswordChanged => "password_changed",
+            MailTemplate::TotpEnabled => "totp_enabled",
+            MailTemplate::TotpDisabled => "totp_disabled",
+            MailTemplate::EmailChanged => "email_changed",
+            MailTemplate::AccountDeleted => "account_deleted",
+            MailTemplate::AuthenticatorAdded => "authenticator_added",
+            MailTemplate::AuthenticatorRemoved => "authenticator_removed",
+            MailTemplate::AccountLocked => "account_locked",
+        }
+    }
+}
+
+/// The parts of a template, stored as `<name>.subject`, `<name>.html` and `<name>.txt`
+#[derive(Clone, Debug)]
+struct TemplateParts {
+    subject: String,
+    html: String,
+    text: String,
+}
+
+/// A rendered mail
+#[derive(Getters, Clone, Debug)]
+#[get = "pub"]
+pub struct RenderedMail {
+    subject: String,
+    html: String,
+    text: String,
+}
+
+macro_rules! builtin {
+    ($locale:literal, $name:literal) => {
+        (
+            ($locale, $name),
+            TemplateParts {
+                subject: include_str!(concat!(
+                    "../../templates/mail/",
+                    $locale,
+                    "/",
+                    $name,
+                    ".subject"
+                ))
+                .trim()
+                .to_string(),
+                html: include_str!(concat!(
+                    "../../templates/mail/",
+                    $locale,
+                    "/",
+                    $name,
+                    ".html"
+                ))
+                .to_string(),
+                text: include_str!(concat!(
+                    "../../templates/mail/",
+                    $locale,
+                    "/",
+                    $name,
+                    ".txt"
+                ))
+                .to_string(),
+            },
+        )
+    };
+}
+
+pub struct TemplateEngine {
+    templates: HashMap<(String, &'static str), TemplateParts>,
+}
+
+impl TemplateEngine {
+    /// Create new TemplateEngine with the builtin templates
+    /// The templates in `TEMPLATE_DIR` (`<locale>/<name>.<subject|html|txt>`) override them
+    pub fn new() -> Self {
+        let mut engine = Self::builtin();
+        if let Ok(directory) = std::env::var("TEMPLATE_DIR") {
+            engine.load_directory(Path::new(directory.as_str()));
+        }
+
+        engine
+    }
+
+    /// Create new TemplateEngine with the builtin templates only
+    pub fn builtin() -> Self {
+        let templates = [
+            builtin!("en", "email_verification"),
+            builtin!("en", "password_reset"),
+            builtin!("en", "email_change"),
+            builtin!("en", "email_change_notice"),
+            builtin!("en", "magic_link"),
+            builtin!("en", "new_login"),
+            builtin!("en", "password_changed"),
+            builtin!("en", "totp_enabled"),
+            builtin!("en", "totp_disabled"),
+            builtin!("en", "email_changed"),
+            builtin!("en", "account_deleted"),
+            builtin!("en", "authenticator_added"),
+            builtin!("en", "authenticator_removed"),
+            builtin!("en", "account_locked"),
+            builtin!("de", "email_verification"),
+            builtin!("de", "password_reset"),
+            builtin!("de", "email_change"),
+            builtin!("de", "email_change_notice"),
+            builtin!("de", "magic_link"),
+            builtin!("de", "new_login"),
+            builtin!("de", "password_changed"),
+            builtin!("de", "totp_enabled"),
+            builtin!("de", "totp_disabled"),
+            builtin!("de", "email_changed"),
+            builtin!("de", "account_deleted"),
+            builtin!("de", "authenticator_added"),
+            builtin!("de", "authenticator_removed"),
+            builtin!("de", "account_locked"),
+        ]
+        .into_iter()
+        .map(|((locale, name), parts)| ((locale.to_string(), name), parts))
+        .collect();
+
+        Self { templates }
+    }
+
+    /// Load the templates of all locales in the given directory
+    pub fn load_directory(&mut self, directory: &Path) {
+        let entries = match std::fs::read_dir(directory) {
+            Ok(entries) => entries,
+            Err(error) => {
+                warn!("Failed to read the template directory: {}", error);
+                return;
+            }
+        };
+
+        for entry in entries.flatten().filter(|entry| entry.path().is_dir()) {
+            let locale = normalize_locale(entry.file_name().to_string_lossy().as_ref());
+
+            for template in MailTemplate::ALL {
+                let read = |extension: &str| {
+                    std::fs::read_to_string(entry.path().join(format!(
+                        "{}.{}",
+                        template.name(),
+                        extension
+                    )))
+                    .ok()
+                };
+
+                // only complete templates can be used
+                match (read("subject"), read("html"), read("txt")) {
+                    (Some(subject), Some(html), Some(text)) => {
+                        self.templates.insert(
+                            (locale.clone(), template.name()),
+                            TemplateParts {
+                                subject: subject.trim().to_string(),
+                                html,
+                                text,
+                            },
+                        );
+                    }
+                    (None, None, None) => {}
+                    _ => warn!(
+                        "Ignoring incomplete template {} for locale {}",
+                        template.name(),
+                        locale
+                    ),
+                }
+            }
+        }
+    }
+
+    /// Render the template in the given locale (falls back to the language and then to english)
+    /// Every `{{key}}` is replaced by the value (html escaped in the html part)
+    pub fn render(
+        &self,
+        template: MailTemplate,
+        locale: &str,
+        values: &[(&str, &str)],
+    ) -> RenderedMail {
+        let locale = normalize_locale(locale);
+        let language = locale.split('-').next().unwrap_or_default().to_string();
+
+        let parts = [locale, language, FALLBACK_LOCALE.to_string()]
+            .into_iter()
+            .find_map(|locale| self.templates.get(&(locale, template.name())))
+            .expect("Missing fallback template");
+
+        RenderedMail {
+            subject: replace(parts.subject.as_str(), values, false),
+            html: replace(parts.html.as_str(), values, true),
+            text: replace(parts.text.as_str(), values, false),
+        }
+    }
+}
+
+/// Lowercase the locale and use `-` as separator (`de_DE` -> `de-de`)
+fn normalize_locale(locale: &str) -> String {
+    locale.trim().to_lowercase().replace('_', "-")
+}
+
+/// Replace the placeholders with the values
+fn replace(template: &str, values: &[(&str, &str)], escape: bool) -> String {
+    values
+        .iter()
+        .fold(template.to_string(), |content, (key, value)| {
+            let value = match escape {
+                true => escape_html(value),
+                false => value.to_string(),
+            };
+
+            content.replace(format!("{{{{{}}}}}", key).as_str(), value.as_str())
+        })
+}
+
+/// Escape the characters with a special meaning in html
+fn escape_html(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#x27;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let engine = TemplateEngine::builtin();
+        let mail = engine.render(
+            MailTemplate::EmailVerification,
+            "en",
+            &[("name", "<b>Nick</b>"), ("link", "https://example.com")],
+        );
+
+        // escaped in html only
+        assert!(mail.html().contains("&lt;b&gt;Nick&lt;/b&gt;"));
+        assert!(mail.text().contains("<b>Nick</b>"));
+        assert!(mail.text().contains("https://example.com"));
+        assert!(!mail.html().contains("{{"));
+    }
+
+    #[test]
+    fn test_builtin_complete() {
+        let engine = TemplateEngine::builtin();
+
+        for template in MailTemplate::ALL {
+            for locale in ["en", "de"] {
+                assert!(engine
+                    .templates
+                    .contains_key(&(locale.to_string(), template.name())));
+            }
+        }
+    }
+
+    #[test]
+    fn test_locale_fallback() {
+        let engine = TemplateEngine::builtin();
+        let english = engine.render(MailTemplate::PasswordReset, "en", &[]);
+
+        // the language is used for regional locales
+        let german = engine.render(MailTemplate::PasswordReset, "de_DE", &[]);
+        assert_ne!(german.subject(), english.subject());
+        // unknown locales fall back to english
+        let unknown = engine.render(MailTemplate::PasswordReset, "xx", &[]);
+        assert_eq!(unknown.subject(), english.subject());
+    }
+
+    #[test]
+    fn test_override() {
+        // write an override
+        let directory = std::env::temp_dir().join("paseto-openid-server-templates");
+        std::fs::create_dir_all(directory.join("fr")).unwrap();
+        for (extension, content) in [
+            ("subject", "Connexion"),
+            ("html", "<p>{{link}}</p>"),
+            ("txt", "{{link}}"),
+        ] {
+            std::fs::write(
+                directory.join(format!("fr/magic_link.{}", extension)),
+                content,
+            )
+            .unwrap();
+        }
+
+        let mut engine = TemplateEngine::builtin();
+        engine.load_directory(directory.as_path());
+        let mail = engine.render(MailTemplate::MagicLink, "fr", &[("link", "x")]);
+        assert_eq!(mail.subject(), "Connexion");
+        assert_eq!(mail.html(), "<p>x</p>");
+        assert_eq!(mail.text(), "x");
+    }
+}
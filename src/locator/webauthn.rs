@@ -0,0 +1,713 @@
+/*
+ * The MIT License (MIT)
+ *
+ * Copyright (c) 2022 Randoooom
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in the
+ * Software without restriction, including without limitation the rights to use, copy,
+ * modify, merge, publish, distribute, sublicense, and/or sell copies of the Software,
+ * and to permit persons to whom the Software is furnished to do so, subject to the
+ * following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+ * EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+ * MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+ * NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT
+ * HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR
+ * IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ *  SOFTWARE.
+ */
+
+use crate::database::client::Client;
+use crate::database::credential::WebauthnCredential;
+use crate::{WEBAUTHN_ORIGIN, WEBAUTHN_RP_ID, WEBAUTHN_RP_NAME};
+use chrono::{DateTime, Duration, Utc};
+use openssl::bn::{BigNum, BigNumContext};
+use openssl::ec::{EcGroup, EcKey, EcPoint};
+use openssl::hash::MessageDigest;
+use openssl::nid::Nid;
+use openssl::pkey::{Id, PKey, Public};
+use openssl::rsa::Rsa;
+use openssl::sign::Verifier;
+use rbatis::Uuid;
+use serde_cbor::Value;
+use std::collections::HashMap;
+
+/// The time the user has to complete a ceremony (in seconds)
+const CEREMONY_LIFETIME: i64 = 300;
+
+/// The supported COSE algorithms
+const ES256: i64 = -7;
+const EDDSA: i64 = -8;
+const RS256: i64 = -257;
+
+/// The authenticator data flags
+const USER_PRESENT: u8 = 0x01;
+const USER_VERIFIED: u8 = 0x04;
+const ATTESTED_CREDENTIAL: u8 = 0x40;
+
+#[derive(Error, Debug)]
+pub enum WebauthnError {
+    #[error("Unknown or expired challenge")]
+    Challenge,
+    #[error("Invalid client data")]
+    ClientData,
+    #[error("Invalid authenticator data")]
+    AuthenticatorData,
+    #[error("User verification required")]
+    UserVerification,
+    #[error("Unsupported public key")]
+    PublicKey,
+    #[error("Invalid signature")]
+    Signature,
+    #[error("Signature counter did not increase")]
+    Counter,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum CeremonyKind {
+    Registration,
+    Authentication,
+}
+
+#[derive(Clone, Debug)]
+struct Ceremony {
+    kind: CeremonyKind,
+    /// the client the ceremony was started for (unknown for passkey logins)
+    sub: Option<Uuid>,
+    expires: DateTime<Utc>,
+}
+
+/// The response of navigator.credentials.create (base64url encoded)
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct AttestationResponse {
+    #[serde(rename = "clientDataJSON")]
+    client_data_json: String,
+    attestation_object: String,
+    #[serde(default)]
+    transports: Vec<String>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug)]
+pub struct RegistrationCredential {
+    id: String,
+    response: AttestationResponse,
+}
+
+/// The response of navigator.credentials.get (base64url encoded)
+#[derive(Deserialize, Serialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct AssertionResponse {
+    #[serde(rename = "clientDataJSON")]
+    client_data_json: String,
+    authenticator_data: String,
+    signature: String,
+    user_handle: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Getters)]
+pub struct AuthenticationCredential {
+    #[get = "pub"]
+    id: String,
+    response: AssertionResponse,
+}
+
+#[derive(Deserialize)]
+struct ClientData {
+    #[serde(rename = "type")]
+    kind: String,
+    challenge: String,
+    origin: String,
+}
+
+struct AuthenticatorData {
+    flags: u8,
+    sign_count: u32,
+    /// the credential id and the COSE key (registration only)
+    credential: Option<(Vec<u8>, Vec<u8>)>,
+}
+
+/// Keeps the pending challenges of the registration and authentication ceremonies
+pub struct WebauthnHandler {
+    ceremonies: HashMap<String, Ceremony>,
+}
+
+impl WebauthnHandler {
+    /// Create new WebauthnHandler instance
+    pub fn new() -> Self {
+        Self {
+            ceremonies: HashMap::new(),
+        }
+    }
+
+    /// Register a new random challenge
+    fn challenge(&mut self, kind: CeremonyKind, sub: Option<Uuid>) -> String {
+        // drop the expired ceremonies
+        let now = Utc::now();
+        self.ceremonies
+            .retain(|_, ceremony| ceremony.expires >= now);
+
+        let mut bytes = [0u8; 32];
+        openssl::rand::rand_bytes(&mut bytes).unwrap();
+        let challenge = encode(bytes.as_slice());
+
+        self.ceremonies.insert(
+            challenge.clone(),
+            Ceremony {
+                kind,
+                sub,
+                expires: now + Duration::seconds(CEREMONY_LIFETIME),
+            },
+        );
+        challenge
+    }
+
+    /// Consume the challenge, it can only be used once
+    fn take(&mut self, challenge: &str, kind: CeremonyKind) -> Result<Option<Uuid>, WebauthnError> {
+        self.ceremonies
+            .remove(challenge)
+            .filter(|ceremony| ceremony.kind == kind && ceremony.expires >= Utc::now())
+            .map(|ceremony| ceremony.sub)
+            .ok_or(WebauthnError::Challenge)
+    }
+
+    /// Start the registration of a new authenticator for the client
+    pub fn creation_options(
+        &mut self,
+        client: &Client,
+        existing: &[WebauthnCredential],
+    ) -> serde_json::Value {
+        let challenge = self.challenge(CeremonyKind::Registration, Some(client.sub().clone()));
+
+        json!({
+            "challenge": challenge,
+            "rp": {"id": WEBAUTHN_RP_ID.as_str(), "name": WEBAUTHN_RP_NAME.as_str()},
+            "user": {
+                "id": encode(client.sub().to_string().as_bytes()),
+                "name": client.nickname(),
+                "displayName": client.name(),
+            },
+            "pubKeyCredParams": [
+                {"type": "public-key", "alg": ES256},
+                {"type": "public-key", "alg": EDDSA},
+                {"type": "public-key", "alg": RS256},
+            ],
+            "timeout": CEREMONY_LIFETIME * 1000,
+            "attestation": "none",
+            "excludeCredentials": descriptors(existing),
+            "authenticatorSelection": {"residentKey": "preferred", "userVerification": "preferred"},
+        })
+    }
+
+    /// Start an authentication, without a client any discoverable credential (passkey) may be used
+    pub fn request_options(
+        &mut self,
+        sub: Option<Uuid>,
+        allowed: &[WebauthnCredential],
+    ) -> serde_json::Value {
+        let challenge = self.challenge(CeremonyKind::Authentication, sub);
+
+        json!({
+            "challenge": challenge,
+            "rpId": WEBAUTHN_RP_ID.as_str(),
+            "timeout": CEREMONY_LIFETIME * 1000,
+            "allowCredentials": descriptors(allowed),
+            "userVerification": "preferred",
+        })
+    }
+
+    /// Verify the registration and build the credential (without persisting it).
+    ///
+    /// Only attestation "none" is requested, so the attestation statement is not verified.
+    pub fn verify_registration(
+        &mut self,
+        sub: &Uuid,
+        name: String,
+        credential: &RegistrationCredential,
+    ) -> Result<WebauthnCredential, WebauthnError> {
+        let client_data = decode(credential.response.client_data_json.as_str())?;
+        self.verify_client_data(
+            client_data.as_slice(),
+            CeremonyKind::Registration,
+            Some(sub),
+        )?;
+
+        // the attestation object is a cbor map (fmt, attStmt, authData)
+        let attestation = decode(credential.response.attestation_object.as_str())?;
+        let attestation = match serde_cbor::from_slice::<Value>(attestation.as_slice()) {
+            Ok(Value::Map(attestation)) => attestation,
+            _ => return Err(WebauthnError::AuthenticatorData),
+        };
+        let authenticator_data = match attestation.get(&Value::Text("authData".to_string())) {
+            Some(Value::Bytes(data)) => parse_authenticator_data(data.as_slice())?,
+            _ => return Err(WebauthnError::AuthenticatorData),
+        };
+        let (credential_id, cose_key) = authenticator_data
+            .credential
+            .ok_or(WebauthnError::AuthenticatorData)?;
+        if encode(credential_id.as_slice()) != credential.id {
+            return Err(WebauthnError::AuthenticatorData);
+        }
+
+        let (algorithm, public_key) = cose_to_public_key(cose_key.as_slice())?;
+
+        Ok(WebauthnCredential::builder()
+            .client(sub.clone())
+            .name(name)
+            .credential_id(credential.id.clone())
+            .public_key(base64::encode(
+                public_key
+                    .public_key_to_der()
+                    .map_err(|_| WebauthnError::PublicKey)?,
+            ))
+            .algorithm(algorithm)
+            .sign_count(authenticator_data.sign_count as i64)
+            .transports(credential.response.transports.join(","))
+            .build())
+    }
+
+    /// Verify the assertion with the stored credential and update its counter.
+    ///
+    /// Passwordless logins require the user verification (pin or biometrics) of the authenticator.
+    pub fn verify_authentication(
+        &mut self,
+        credential: &AuthenticationCredential,
+        stored: &mut WebauthnCredential,
+        require_user_verification: bool,
+    ) -> Result<(), WebauthnError> {
+        let client_data = decode(credential.response.client_data_json.as_str())?;
+        self.verify_client_data(
+            client_data.as_slice(),
+            CeremonyKind::Authentication,
+            Some(stored.client()),
+        )?;
+
+        // the user handle identifies the owner of discoverable credentials
+        if let Some(user_handle) = &credential.response.user_handle {
+            if decode(user_handle.as_str())? != stored.client().to_string().into_bytes() {
+                return Err(WebauthnError::AuthenticatorData);
+            }
+        }
+
+        let raw = decode(credential.response.authenticator_data.as_str())?;
+        let authenticator_data = parse_authenticator_data(raw.as_slice())?;
+        if require_user_verification && authenticator_data.flags & USER_VERIFIED == 0 {
+            return Err(WebauthnError::UserVerification);
+        }
+
+        // the signature covers the authenticator data and the hash of the client data
+        let mut signed = raw;
+        signed.extend_from_slice(&openssl::sha::sha256(client_data.as_slice()));
+        let public_key = base64::decode(stored.public_key())
+            .ok()
+            .and_then(|der| PKey::public_key_from_der(der.as_slice()).ok())
+            .ok_or(WebauthnError::PublicKey)?;
+        let signature = decode(credential.response.signature.as_str())?;
+        if !verify_signature(
+            *stored.algorithm(),
+            &public_key,
+            signed.as_slice(),
+            signature.as_slice(),
+        ) {
+            return Err(WebauthnError::Signature);
+        }
+
+        // a counter which does not increase indicates a cloned authenticator
+        let sign_count = authenticator_data.sign_count as i64;
+        if (sign_count != 0 || *stored.sign_count() != 0) && sign_count <= *stored.sign_count() {
+            return Err(WebauthnError::Counter);
+        }
+        stored.set_sign_count(sign_count);
+
+        Ok(())
+    }
+
+    /// Checks the type, origin and challenge of the client data
+    fn verify_client_data(
+        &mut self,
+        raw: &[u8],
+        kind: CeremonyKind,
+        sub: Option<&Uuid>,
+    ) -> Result<(), WebauthnError> {
+        let client_data =
+            serde_json::from_slice::<ClientData>(raw).map_err(|_| WebauthnError::ClientData)?;
+        let expected = match kind {
+            CeremonyKind::Registration => "webauthn.create",
+            CeremonyKind::Authentication => "webauthn.get",
+        };
+
+        // consume the challenge in any case
+        let bound = self.take(client_data.challenge.as_str(), kind)?;
+        if client_data.kind != expected || client_data.origin != WEBAUTHN_ORIGIN.as_str() {
+            return Err(WebauthnError::ClientData);
+        }
+        // the challenge may be bound to a client
+        if let (Some(bound), Some(sub)) = (bound, sub) {
+            if &bound != sub {
+                return Err(WebauthnError::Challenge);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The credential descriptors used for excludeCredentials and allowCredentials
+fn descriptors(credentials: &[WebauthnCredential]) -> Vec<serde_json::Value> {
+    credentials
+        .iter()
+        .map(|credential| {
+            json!({
+                "type": "public-key",
+                "id": credential.credential_id(),
+                "transports": credential.transport_list(),
+            })
+        })
+        .collect()
+}
+
+fn encode(data: &[u8]) -> String {
+    base64::encode_config(data, base64::URL_SAFE_NO_PAD)
+}
+
+fn decode(data: &str) -> Result<Vec<u8>, WebauthnError> {
+    base64::decode_config(data, base64::URL_SAFE_NO_PAD).map_err(|_| WebauthnError::ClientData)
+}
+
+/// Parse the authenticator data and check the rp id hash and the user presence
+fn parse_authenticator_data(data: &[u8]) -> Result<AuthenticatorData, WebauthnError> {
+    // rpIdHash (32), flags (1), signCount (4)
+    if data.len() < 37 {
+        return Err(WebauthnError::AuthenticatorData);
+    }
+    let rp_id_hash = openssl::sha::sha256(WEBAUTHN_RP_ID.as_bytes());
+    if !openssl::memcmp::eq(&data[..32], &rp_id_hash) {
+        return Err(WebauthnError::AuthenticatorData);
+    }
+    let flags = data[32];
+    if flags & USER_PRESENT == 0 {
+        return Err(WebauthnError::AuthenticatorData);
+    }
+    let sign_count = u32::from_be_bytes([data[33], data[34], data[35], data[36]]);
+
+    // aaguid (16), credentialIdLength (2), credentialId, credentialPublicKey
+    let credential = if flags & ATTESTED_CREDENTIAL != 0 {
+        let rest = &data[37..];
+        if rest.len() < 18 {
+            return Err(WebauthnError::AuthenticatorData);
+        }
+        let length = u16::from_be_bytes([rest[16], rest[17]]) as usize;
+        if rest.len() < 18 + length {
+            return Err(WebauthnError::AuthenticatorData);
+        }
+        let credential_id = rest[18..18 + length].to_vec();
+
+        // the key is followed by the (optional) extensions
+        let key = &rest[18 + length..];
+        let mut deserializer = serde_cbor::Deserializer::from_slice(key);
+        let _: Value = serde::Deserialize::deserialize(&mut deserializer)
+            .map_err(|_| WebauthnError::PublicKey)?;
+        let end = deserializer.byte_offset();
+
+        Some((credential_id, key[..end].to_vec()))
+    } else {
+        None
+    };
+
+    Ok(AuthenticatorData {
+        flags,
+        sign_count,
+        credential,
+    })
+}
+
+/// Convert the COSE key into its algorithm and the openssl key
+fn cose_to_public_key(cose: &[u8]) -> Result<(i64, PKey<Public>), WebauthnError> {
+    let key = match serde_cbor::from_slice::<Value>(cose) {
+        Ok(Value::Map(key)) => key,
+        _ => return Err(WebauthnError::PublicKey),
+    };
+    let integer = |label: i128| match key.get(&Value::Integer(label)) {
+        Some(Value::Integer(value)) => Some(*value as i64),
+        _ => None,
+    };
+    let bytes = |label: i128| match key.get(&Value::Integer(label)) {
+        Some(Value::Bytes(value)) => Some(value.as_slice()),
+        _ => None,
+    };
+
+    let algorithm = integer(3).ok_or(WebauthnError::PublicKey)?;
+    let public_key = match (algorithm, integer(1), integer(-1)) {
+        // EC2 on P-256
+        (ES256, Some(2), Some(1)) => {
+            let (x, y) = bytes(-2).zip(bytes(-3)).ok_or(WebauthnError::PublicKey)?;
+            let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+            let mut point = vec![0x04];
+            point.extend_from_slice(x);
+            point.extend_from_slice(y);
+            let mut context = BigNumContext::new().unwrap();
+            EcPoint::from_bytes(&group, point.as_slice(), &mut context)
+                .and_then(|point| EcKey::from_public_key(&group, &point))
+                .and_then(PKey::from_ec_key)
+                .ok()
+        }
+        // OKP with Ed25519
+        (EDDSA, Some(1), Some(6)) => {
+            bytes(-2).and_then(|x| PKey::public_key_from_raw_bytes(x, Id::ED25519).ok())
+        }
+        // RSA
+        (RS256, Some(3), _) => bytes(-1).zip(bytes(-2)).and_then(|(n, e)| {
+            Rsa::from_public_components(BigNum::from_slice(n).ok()?, BigNum::from_slice(e).ok()?)
+                .and_then(PKey::from_rsa)
+                .ok()
+        }),
+        _ => None,
+    };
+
+    public_key
+        .map(|public_key| (algorithm, public_key))
+        .ok_or(WebauthnError::PublicKey)
+}
+
+fn verify_signature(algorithm: i64, key: &PKey<Public>, data: &[u8], signature: &[u8]) -> bool {
+    match algorithm {
+        EDDSA => Verifier::new_without_digest(key)
+            .and_then(|mut verifier| verifier.verify_oneshot(signature, data))
+            .unwrap_or(false),
+        ES256 | RS256 => Verifier::new(MessageDigest::sha256(), key)
+            .and_then(|mut verifier| {
+                verifier.update(data)?;
+                verifier.verify(signature)
+            })
+            .unwrap_or(false),
+        _ => false,
+    }
+}
+
+/// A software authenticator with a P-256 key, used to run the ceremonies in the tests
+#[cfg(test)]
+pub struct SoftAuthenticator {
+    key: PKey<openssl::pkey::Private>,
+    credential_id: Vec<u8>,
+    user_handle: Option<String>,
+    sign_count: u32,
+    /// whether the user verification flag is set
+    pub user_verification: bool,
+}
+
+#[cfg(test)]
+impl SoftAuthenticator {
+    pub fn new() -> Self {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        let key = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+        let mut credential_id = vec![0u8; 16];
+        openssl::rand::rand_bytes(&mut credential_id).unwrap();
+
+        Self {
+            key,
+            credential_id,
+            user_handle: None,
+            sign_count: 0,
+            user_verification: true,
+        }
+    }
+
+    fn authenticator_data(&mut self, rp_id: &str, attested: bool) -> Vec<u8> {
+        let mut flags = USER_PRESENT;
+        if self.user_verification {
+            flags |= USER_VERIFIED;
+        }
+        if attested {
+            flags |= ATTESTED_CREDENTIAL;
+        }
+        self.sign_count += 1;
+
+        let mut data = openssl::sha::sha256(rp_id.as_bytes()).to_vec();
+        data.push(flags);
+        data.extend_from_slice(&self.sign_count.to_be_bytes());
+        if attested {
+            // the aaguid is empty for attestation none
+            data.extend_from_slice(&[0u8; 16]);
+            data.extend_from_slice(&(self.credential_id.len() as u16).to_be_bytes());
+            data.extend_from_slice(self.credential_id.as_slice());
+            data.extend_from_slice(self.cose_key().as_slice());
+        }
+        data
+    }
+
+    fn cose_key(&self) -> Vec<u8> {
+        let key = self.key.ec_key().unwrap();
+        let mut context = BigNumContext::new().unwrap();
+        let mut x = BigNum::new().unwrap();
+        let mut y = BigNum::new().unwrap();
+        key.public_key()
+            .affine_coordinates_gfp(key.group(), &mut x, &mut y, &mut context)
+            .unwrap();
+
+        let mut map = std::collections::BTreeMap::new();
+        map.insert(Value::Integer(1), Value::Integer(2));
+        map.insert(Value::Integer(3), Value::Integer(ES256 as i128));
+        map.insert(Value::Integer(-1), Value::Integer(1));
+        map.insert(
+            Value::Integer(-2),
+            Value::Bytes(x.to_vec_padded(32).unwrap()),
+        );
+        map.insert(
+            Value::Integer(-3),
+            Value::Bytes(y.to_vec_padded(32).unwrap()),
+        );
+        serde_cbor::to_vec(&Value::Map(map)).unwrap()
+    }
+
+    fn client_data(kind: &str, options: &serde_json::Value, origin: &str) -> Vec<u8> {
+        json!({
+            "type": kind,
+            "challenge": options["challenge"],
+            "origin": origin,
+        })
+        .to_string()
+        .into_bytes()
+    }
+
+    /// Create a new credential for the given creation options
+    pub fn create(&mut self, options: &serde_json::Value, origin: &str) -> RegistrationCredential {
+        self.user_handle = options["user"]["id"].as_str().map(str::to_string);
+        let rp_id = options["rp"]["id"].as_str().unwrap();
+
+        let mut attestation = std::collections::BTreeMap::new();
+        attestation.insert(Value::Text("fmt".into()), Value::Text("none".into()));
+        attestation.insert(
+            Value::Text("attStmt".into()),
+            Value::Map(std::collections::BTreeMap::new()),
+        );
+        attestation.insert(
+            Value::Text("authData".into()),
+            Value::Bytes(self.authenticator_data(rp_id, true)),
+        );
+
+        RegistrationCredential {
+            id: encode(self.credential_id.as_slice()),
+            response: AttestationResponse {
+                client_data_json: encode(
+                    Self::client_data("webauthn.create", options, origin).as_slice(),
+                ),
+                attestation_object: encode(
+                    serde_cbor::to_vec(&Value::Map(attestation))
+                        .unwrap()
+                        .as_slice(),
+                ),
+                transports: vec!["internal".to_string()],
+            },
+        }
+    }
+
+    /// Sign the challenge of the given request options
+    pub fn get(&mut self, options: &serde_json::Value, origin: &str) -> AuthenticationCredential {
+        let rp_id = options["rpId"].as_str().unwrap();
+        let client_data = Self::client_data("webauthn.get", options, origin);
+        let authenticator_data = self.authenticator_data(rp_id, false);
+
+        let mut signer = openssl::sign::Signer::new(MessageDigest::sha256(), &self.key).unwrap();
+        signer.update(authenticator_data.as_slice()).unwrap();
+        signer
+            .update(&openssl::sha::sha256(client_data.as_slice()))
+            .unwrap();
+
+        AuthenticationCredential {
+            id: encode(self.credential_id.as_slice()),
+            response: AssertionResponse {
+                client_data_json: encode(client_data.as_slice()),
+                authenticator_data: encode(authenticator_data.as_slice()),
+                signature: encode(signer.sign_to_vec().unwrap().as_slice()),
+                user_handle: self.user_handle.clone(),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ceremonies() {
+        let mut handler = WebauthnHandler::new();
+        let mut authenticator = SoftAuthenticator::new();
+        let client = Client::default();
+
+        // register
+        let options = handler.creation_options(&client, &[]);
+        let response = authenticator.create(&options, WEBAUTHN_ORIGIN.as_str());
+        let mut credential = handler
+            .verify_registration(client.sub(), "Laptop".to_string(), &response)
+            .unwrap();
+        assert_eq!(credential.algorithm(), &ES256);
+        assert_eq!(credential.transport_list(), vec!["internal".to_string()]);
+
+        // the challenge can only be used once
+        assert!(handler
+            .verify_registration(client.sub(), "Laptop".to_string(), &response)
+            .is_err());
+
+        // authenticate
+        let options = handler.request_options(None, &[]);
+        let assertion = authenticator.get(&options, WEBAUTHN_ORIGIN.as_str());
+        handler
+            .verify_authentication(&assertion, &mut credential, true)
+            .unwrap();
+        assert_eq!(credential.sign_count(), &2);
+
+        // the counter has to increase
+        let options = handler.request_options(None, &[]);
+        let assertion = authenticator.get(&options, WEBAUTHN_ORIGIN.as_str());
+        credential.set_sign_count(10);
+        assert!(matches!(
+            handler.verify_authentication(&assertion, &mut credential, true),
+            Err(WebauthnError::Counter)
+        ));
+    }
+
+    #[test]
+    fn test_verification() {
+        let mut handler = WebauthnHandler::new();
+        let mut authenticator = SoftAuthenticator::new();
+        let client = Client::default();
+
+        let options = handler.creation_options(&client, &[]);
+        let response = authenticator.create(&options, WEBAUTHN_ORIGIN.as_str());
+        let mut credential = handler
+            .verify_registration(client.sub(), "Key".to_string(), &response)
+            .unwrap();
+
+        // the origin has to match
+        let options = handler.request_options(None, &[]);
+        let assertion = authenticator.get(&options, "https://evil.example.com");
+        assert!(matches!(
+            handler.verify_authentication(&assertion, &mut credential, false),
+            Err(WebauthnError::ClientData)
+        ));
+
+        // passwordless logins require the user verification
+        authenticator.user_verification = false;
+        let options = handler.request_options(None, &[]);
+        let assertion = authenticator.get(&options, WEBAUTHN_ORIGIN.as_str());
+        assert!(matches!(
+            handler.verify_authentication(&assertion, &mut credential, true),
+            Err(WebauthnError::UserVerification)
+        ));
+
+        // the challenge has to be bound to the owner
+        let options = handler.request_options(Some(Uuid::new()), &[]);
+        let assertion = authenticator.get(&options, WEBAUTHN_ORIGIN.as_str());
+        assert!(matches!(
+            handler.verify_authentication(&assertion, &mut credential, false),
+            Err(WebauthnError::Challenge)
+        ));
+    }
+}
@@ -43,14 +43,15 @@ extern crate thiserror;
 #[macro_use]
 extern crate async_trait;
 
-use crate::middleware::require_session;
-use axum::http::{header, Method};
+use crate::middleware::rate_limit::{RateLimit, RateLimitLayer};
+use crate::middleware::{client_address, client_certificate, require_admin, require_session};
+use axum::http::{header, HeaderName, Method};
 use axum::middleware::from_fn;
 use axum::{
     routing::{get, post, put},
     Extension, Router,
 };
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 use tower_http::cors::{CorsLayer, Origin};
 use tower_http::trace::TraceLayer;
 
@@ -63,14 +64,102 @@ mod openid;
 mod routes;
 #[cfg(test)]
 mod tests;
+mod tls;
+mod worker;
 
 lazy_static! {
     pub static ref ROOT: String = std::env::var("ROOT").unwrap();
+    pub static ref ISSUER: String = std::env::var("ISSUER").unwrap();
     pub static ref TOTP_NAME: String = std::env::var("TOTP_NAME").unwrap();
+    pub static ref TOTP_SKEW: i64 = std::env::var("TOTP_SKEW")
+        .map(|steps| steps.parse().unwrap())
+        .unwrap_or(1);
+    pub static ref PASSWORD_HASH_MEMORY: u32 = std::env::var("PASSWORD_HASH_MEMORY")
+        .map(|memory| memory.parse().unwrap())
+        .unwrap_or(19456);
+    pub static ref PASSWORD_HASH_ITERATIONS: u32 = std::env::var("PASSWORD_HASH_ITERATIONS")
+        .map(|iterations| iterations.parse().unwrap())
+        .unwrap_or(2);
+    pub static ref PASSWORD_HASH_PARALLELISM: u32 = std::env::var("PASSWORD_HASH_PARALLELISM")
+        .map(|lanes| lanes.parse().unwrap())
+        .unwrap_or(1);
+    pub static ref PASSWORD_PEPPERS: Vec<(u32, Vec<u8>)> = std::env::var("PASSWORD_PEPPERS")
+        .map(|peppers| {
+            peppers
+                .split(',')
+                .filter(|pepper| !pepper.trim().is_empty())
+                .map(|pepper| {
+                    let (version, secret) = pepper.trim().split_once(':').expect("Invalid pepper");
+                    (
+                        version.parse().expect("Invalid pepper version"),
+                        base64::decode(secret).expect("Invalid pepper"),
+                    )
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    pub static ref PASSWORD_MIN_SCORE: u8 = std::env::var("PASSWORD_MIN_SCORE")
+        .map(|score| score.parse().unwrap())
+        .unwrap_or(3);
+    pub static ref PASSWORD_MIN_LENGTH: usize = std::env::var("PASSWORD_MIN_LENGTH")
+        .map(|length| length.parse().unwrap())
+        .unwrap_or(8);
+    pub static ref PASSWORD_MAX_LENGTH: usize = std::env::var("PASSWORD_MAX_LENGTH")
+        .map(|length| length.parse().unwrap())
+        .unwrap_or(128);
+    pub static ref BREACHED_PASSWORDS_PATH: Option<String> =
+        std::env::var("BREACHED_PASSWORDS_PATH").ok();
     pub static ref LOCAL_SESSION_LENGTH: usize = std::env::var("LOCAL_SESSION_LENGTH")
         .unwrap()
         .parse()
         .unwrap();
+    pub static ref VERIFICATION_RESEND_INTERVAL: i64 =
+        std::env::var("VERIFICATION_RESEND_INTERVAL")
+            .unwrap()
+            .parse()
+            .unwrap();
+    pub static ref LOGIN_REQUIRE_VERIFIED_EMAIL: bool =
+        std::env::var("LOGIN_REQUIRE_VERIFIED_EMAIL")
+            .map(|required| required.parse().unwrap())
+            .unwrap_or(false);
+    pub static ref LOGIN_DELAY_AFTER: i32 = std::env::var("LOGIN_DELAY_AFTER")
+        .map(|failures| failures.parse().unwrap())
+        .unwrap_or(3);
+    pub static ref LOGIN_LOCK_AFTER: i32 = std::env::var("LOGIN_LOCK_AFTER")
+        .map(|failures| failures.parse().unwrap())
+        .unwrap_or(10);
+    pub static ref LOGIN_LOCK_DURATION: i64 = std::env::var("LOGIN_LOCK_DURATION")
+        .map(|minutes| minutes.parse().unwrap())
+        .unwrap_or(15);
+    pub static ref ADMIN_KEY: String = std::env::var("ADMIN_KEY").unwrap();
+    pub static ref TRUSTED_PROXIES: Vec<IpAddr> = std::env::var("TRUSTED_PROXIES")
+        .map(|proxies| {
+            proxies
+                .split(',')
+                .filter(|proxy| !proxy.trim().is_empty())
+                .map(|proxy| proxy.trim().parse().expect("Invalid trusted proxy"))
+                .collect()
+        })
+        .unwrap_or_default();
+    pub static ref CLIENT_CERT_HEADER: String =
+        std::env::var("CLIENT_CERT_HEADER").unwrap_or_else(|_| "X-Client-Cert".to_string());
+    pub static ref TLS_CLIENT_CA_PATH: Option<String> = std::env::var("TLS_CLIENT_CA_PATH").ok();
+    pub static ref WEBAUTHN_ORIGIN: String =
+        std::env::var("WEBAUTHN_ORIGIN").unwrap_or_else(|_| ROOT.clone());
+    pub static ref WEBAUTHN_RP_ID: String = std::env::var("WEBAUTHN_RP_ID").unwrap_or_else(|_| {
+        reqwest::Url::parse(WEBAUTHN_ORIGIN.as_str())
+            .ok()
+            .and_then(|origin| origin.host_str().map(str::to_string))
+            .expect("Invalid WEBAUTHN_ORIGIN")
+    });
+    pub static ref WEBAUTHN_RP_NAME: String =
+        std::env::var("WEBAUTHN_RP_NAME").unwrap_or_else(|_| TOTP_NAME.clone());
+    pub static ref MAIL_MAX_ATTEMPTS: i32 = std::env::var("MAIL_MAX_ATTEMPTS")
+        .map(|attempts| attempts.parse().unwrap())
+        .unwrap_or(8);
+    pub static ref MAIL_POLL_INTERVAL: u64 = std::env::var("MAIL_POLL_INTERVAL")
+        .map(|interval| interval.parse().unwrap())
+        .unwrap_or(10);
 }
 
 #[tokio::main]
@@ -87,32 +176,115 @@ async fn main() {
         [127, 0, 0, 1],
         std::env::var("PORT").unwrap().parse::<u16>().unwrap(),
     ));
+    // deliver the queued mails in the background
+    let mail = locator::mail::MailSender::new()
+        .unwrap_or_else(|error| panic!("Failed to setup the mail transport: {}", error));
+    let worker = worker::MailWorker::new(database::establish_connection().await, mail);
+    tokio::spawn(worker.run());
+
     // run
-    info!("Axum server listening on {}", address);
-    axum::Server::bind(&address)
-        .serve(app().await.into_make_service())
-        .await
-        .unwrap();
+    let app = app().await;
+    match (
+        std::env::var("TLS_CERTIFICATE_PATH"),
+        std::env::var("TLS_KEY_PATH"),
+    ) {
+        (Ok(certificate), Ok(key)) => {
+            info!("Axum server listening on {} (tls)", address);
+            tls::serve(app, address, certificate.as_str(), key.as_str()).await;
+        }
+        _ => {
+            info!("Axum server listening on {}", address);
+            axum::Server::bind(&address)
+                .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+                .await
+                .unwrap();
+        }
+    }
 }
 
 async fn app() -> Router {
     // init the locator
     let locator = locator::Locator::new().await;
 
+    router(locator)
+}
+
+/// Build the routes around the given locator
+fn router(locator: locator::LocatorPointer) -> Router {
+    // the rate limits of the public route groups
+    let login = RateLimitLayer::new(RateLimit::from_env("LOGIN", RateLimit::new(10, 10)));
+    let signup = RateLimitLayer::new(RateLimit::from_env("SIGNUP", RateLimit::new(5, 5)));
+    let token = RateLimitLayer::new(RateLimit::from_env("TOKEN", RateLimit::new(60, 120)));
+
     // build axum
     Router::new()
-        .route("/auth/login", post(routes::authentication::post_login))
-        .route("/auth/signup", post(routes::authentication::post_signup))
+        .route(
+            "/auth/login",
+            post(routes::authentication::post_login).layer(login.clone()),
+        )
+        .route(
+            "/auth/signup",
+            post(routes::authentication::post_signup).layer(signup),
+        )
+        .route(
+            "/auth/verify_email",
+            post(routes::authentication::post_verify_email).layer(login.clone()),
+        )
+        .route(
+            "/auth/verify_email/resend",
+            post(routes::authentication::post_resend_verification).layer(from_fn(require_session)),
+        )
         .route(
             "/auth/password",
             put(routes::authentication::put_password).layer(from_fn(require_session)),
         )
+        .route(
+            "/auth/password/reset/request",
+            post(routes::authentication::post_request_password_reset).layer(login.clone()),
+        )
+        .route(
+            "/auth/password/reset",
+            post(routes::authentication::post_reset_password).layer(login.clone()),
+        )
+        .route(
+            "/auth/unlock",
+            post(routes::authentication::post_unlock).layer(login.clone()),
+        )
+        .route(
+            "/auth/magic_link",
+            post(routes::authentication::post_request_magic_link).layer(login.clone()),
+        )
+        .route(
+            "/auth/magic_link/redeem",
+            post(routes::authentication::post_redeem_magic_link).layer(login.clone()),
+        )
+        .route(
+            "/auth/webauthn/login/start",
+            post(routes::webauthn::post_start_authentication).layer(login.clone()),
+        )
+        .route(
+            "/auth/webauthn/login/finish",
+            post(routes::webauthn::post_finish_authentication).layer(login),
+        )
+        .route(
+            "/auth/webauthn/register/start",
+            post(routes::webauthn::post_start_registration).layer(from_fn(require_session)),
+        )
+        .route(
+            "/auth/webauthn/register/finish",
+            post(routes::webauthn::post_finish_registration).layer(from_fn(require_session)),
+        )
         .route(
             "/auth/totp",
             post(routes::authentication::post_activate_totp)
                 .get(routes::authentication::get_qr_code)
                 .layer(from_fn(require_session)),
         )
+        .route(
+            "/auth/totp/recovery_codes",
+            post(routes::authentication::post_regenerate_recovery_codes)
+                .layer(from_fn(require_session)),
+        )
         .route(
             "/auth/totp/disable",
             post(routes::authentication::post_disable_totp).layer(from_fn(require_session)),
@@ -131,10 +303,74 @@ async fn app() -> Router {
             "/client/me/address",
             put(routes::client::put_address).layer(from_fn(require_session)),
         )
+        .route(
+            "/client/me/email",
+            put(routes::client::put_email).layer(from_fn(require_session)),
+        )
+        .route(
+            "/client/me/email/confirm",
+            post(routes::client::post_confirm_email),
+        )
+        .route(
+            "/client/me/email/cancel",
+            post(routes::client::post_cancel_email_change),
+        )
+        .route(
+            "/client/me/phone/verify/request",
+            post(routes::client::post_request_phone_verification).layer(from_fn(require_session)),
+        )
+        .route(
+            "/client/me/phone/verify",
+            post(routes::client::post_verify_phone).layer(from_fn(require_session)),
+        )
+        .route(
+            "/client/me/authenticators",
+            get(routes::webauthn::get_authenticators).layer(from_fn(require_session)),
+        )
+        .route(
+            "/client/me/authenticators/:uuid",
+            put(routes::webauthn::put_authenticator).layer(from_fn(require_session)),
+        )
+        .route(
+            "/client/me/authenticators/:uuid/delete",
+            post(routes::webauthn::post_delete_authenticator).layer(from_fn(require_session)),
+        )
         .route(
             "/client/delete",
             post(routes::client::post_delete).layer(from_fn(require_session)),
         )
+        .route(
+            "/token",
+            post(routes::openid::post_token).layer(token.clone()),
+        )
+        .route(
+            "/introspect",
+            post(routes::openid::post_introspect).layer(token),
+        )
+        .route("/userinfo", get(routes::openid::get_userinfo))
+        .route("/keys", get(routes::keys::get_keys))
+        .route(
+            "/admin/keys",
+            post(routes::keys::post_generate_key).layer(from_fn(require_admin)),
+        )
+        .route(
+            "/admin/keys/promote",
+            post(routes::keys::post_promote_key).layer(from_fn(require_admin)),
+        )
+        .route(
+            "/admin/applications",
+            post(routes::application::post_application).layer(from_fn(require_admin)),
+        )
+        .route(
+            "/admin/unlock",
+            post(routes::authentication::post_admin_unlock).layer(from_fn(require_admin)),
+        )
+        .route(
+            "/admin/outbox",
+            get(routes::outbox::get_outbox).layer(from_fn(require_admin)),
+        )
+        .layer(from_fn(client_certificate))
+        .layer(from_fn(client_address))
         .layer(Extension(locator))
         // enable CORS
         .layer(
@@ -148,7 +384,11 @@ async fn app() -> Router {
                     Method::HEAD,
                     Method::OPTIONS,
                 ])
-                .allow_headers(vec![header::AUTHORIZATION, header::CONTENT_TYPE]),
+                .allow_headers(vec![
+                    header::AUTHORIZATION,
+                    header::CONTENT_TYPE,
+                    HeaderName::from_static("dpop"),
+                ]),
         )
         // enable logging
         .layer(TraceLayer::new_for_http())
@@ -26,15 +26,25 @@
 use crate::database::client::Client;
 use crate::error::ResponseError;
 use crate::locator::LocatorPointer;
+use crate::openid::mtls::ClientCertificate;
+use crate::{ADMIN_KEY, CLIENT_CERT_HEADER, TRUSTED_PROXIES};
+use axum::extract::ConnectInfo;
 use axum::http::header::AUTHORIZATION;
-use axum::http::Request;
+use axum::http::{HeaderMap, Request};
 use axum::middleware::Next;
 use axum::response::IntoResponse;
 use rbatis::crud::CRUD;
+use std::net::{IpAddr, SocketAddr};
+
+pub mod rate_limit;
 
 #[derive(Clone)]
 pub struct SessionId(pub String);
 
+/// The address of the user agent (resolved through the trusted proxies)
+#[derive(Clone, Copy, Debug)]
+pub struct ClientAddress(pub IpAddr);
+
 pub async fn require_session<B>(mut request: Request<B>, next: Next<B>) -> impl IntoResponse {
     match request.headers().get(AUTHORIZATION).cloned() {
         Some(value) => {
@@ -74,3 +84,100 @@ pub async fn require_session<B>(mut request: Request<B>, next: Next<B>) -> impl
         None => ResponseError::Unauthorized.into_response(),
     }
 }
+
+pub async fn require_admin<B>(request: Request<B>, next: Next<B>) -> impl IntoResponse {
+    // get the given key
+    let key = request
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+
+    // compare in constant time
+    if key.len() == ADMIN_KEY.len() && openssl::memcmp::eq(key.as_bytes(), ADMIN_KEY.as_bytes()) {
+        return next.run(request).await;
+    }
+
+    ResponseError::Unauthorized.into_response()
+}
+
+/// Resolve the address of the user agent, `X-Forwarded-For` is only used behind trusted proxies
+pub fn resolve_address(headers: &HeaderMap, peer: IpAddr, trusted: &[IpAddr]) -> IpAddr {
+    let forwarded = headers
+        .get("X-Forwarded-For")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+
+    // walk back from the peer until the first untrusted hop
+    let mut address = peer;
+    for hop in forwarded.split(',').rev() {
+        if !trusted.contains(&address) {
+            break;
+        }
+        match hop.trim().parse() {
+            Ok(hop) => address = hop,
+            Err(_) => break,
+        }
+    }
+
+    address
+}
+
+/// Provide the address of the user agent as ClientAddress
+pub async fn client_address<B>(mut request: Request<B>, next: Next<B>) -> impl IntoResponse {
+    let peer = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|info| info.0.ip());
+
+    if let Some(peer) = peer {
+        let address = resolve_address(request.headers(), peer, TRUSTED_PROXIES.as_slice());
+        request.extensions_mut().insert(ClientAddress(address));
+    }
+
+    next.run(request).await
+}
+
+/// Provide the client certificate of the tls connection or the one forwarded by a trusted proxy
+pub async fn client_certificate<B>(mut request: Request<B>, next: Next<B>) -> impl IntoResponse {
+    // the certificate of the own tls connection takes precedence
+    if request.extensions().get::<ClientCertificate>().is_none() {
+        let peer = request
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|info| info.0.ip());
+
+        if let Some(certificate) = ClientCertificate::from_headers(
+            request.headers(),
+            CLIENT_CERT_HEADER.as_str(),
+            peer,
+            TRUSTED_PROXIES.as_slice(),
+        ) {
+            request.extensions_mut().insert(certificate);
+        }
+    }
+
+    next.run(request).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_address() {
+        let proxy: IpAddr = "10.0.0.1".parse().unwrap();
+        let client: IpAddr = "203.0.113.7".parse().unwrap();
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "X-Forwarded-For",
+            "198.51.100.1, 203.0.113.7, 10.0.0.2".parse().unwrap(),
+        );
+
+        // ignored for untrusted peers
+        assert_eq!(resolve_address(&headers, client, &[proxy]), client);
+        // the first untrusted hop from the right
+        let trusted = [proxy, "10.0.0.2".parse().unwrap()];
+        assert_eq!(resolve_address(&headers, proxy, &trusted), client);
+    }
+}
@@ -0,0 +1,235 @@
+/*
+ * The MIT License (MIT)
+ *
+ * Copyright (c) 2022 Randoooom
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in the
+ * Software without restriction, including without limitation the rights to use, copy,
+ * modify, merge, publish, distribute, sublicense, and/or sell copies of the Software,
+ * and to permit persons to whom the Software is furnished to do so, subject to the
+ * following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+ * EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+ * MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+ * NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT
+ * HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR
+ * IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ *  SOFTWARE.
+ */
+
+use crate::error::ResponseError;
+use crate::middleware::ClientAddress;
+use axum::http::Request;
+use axum::response::{IntoResponse, Response};
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Instant;
+use tower::{Layer, Service};
+
+/// The number of tracked addresses before the full buckets are dropped
+const PRUNE_THRESHOLD: usize = 10_000;
+
+/// The limit of a route group
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RateLimit {
+    /// the size of the bucket
+    burst: u32,
+    /// the refill of the bucket
+    per_minute: u32,
+}
+
+impl RateLimit {
+    pub fn new(burst: u32, per_minute: u32) -> Self {
+        Self { burst, per_minute }
+    }
+
+    /// Read `RATE_LIMIT_<GROUP>` as `<burst>/<per minute>`, `0` disables the limit
+    pub fn from_env(group: &str, default: Self) -> Option<Self> {
+        let value = match std::env::var(format!("RATE_LIMIT_{}", group)) {
+            Ok(value) => value,
+            Err(_) => return Some(default),
+        };
+        if value.trim() == "0" {
+            return None;
+        }
+
+        let (burst, per_minute) = value
+            .split_once('/')
+            .unwrap_or_else(|| panic!("Invalid RATE_LIMIT_{}", group));
+        Some(Self::new(
+            burst.trim().parse().expect("Invalid rate limit burst"),
+            per_minute
+                .trim()
+                .parse()
+                .expect("Invalid rate limit refill"),
+        ))
+    }
+}
+
+struct TokenBucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    /// Refill the bucket for the elapsed time
+    fn refill(&mut self, limit: &RateLimit, now: Instant) {
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens =
+            (self.tokens + elapsed * limit.per_minute as f64 / 60.0).min(limit.burst as f64);
+        self.updated = now;
+    }
+}
+
+/// Token buckets per client address, shared by all routes of the group
+#[derive(Clone)]
+pub struct RateLimitLayer {
+    limit: Option<RateLimit>,
+    buckets: Arc<Mutex<HashMap<IpAddr, TokenBucket>>>,
+}
+
+impl RateLimitLayer {
+    pub fn new(limit: Option<RateLimit>) -> Self {
+        Self {
+            limit,
+            buckets: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Take a token from the bucket of the address
+    fn acquire(&self, address: IpAddr) -> bool {
+        let limit = match &self.limit {
+            Some(limit) => limit,
+            None => return true,
+        };
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap();
+
+        // forget the addresses which would have a full bucket anyway
+        if buckets.len() > PRUNE_THRESHOLD {
+            buckets.retain(|_, bucket| {
+                bucket.refill(limit, now);
+                bucket.tokens < limit.burst as f64
+            });
+        }
+
+        let bucket = buckets.entry(address).or_insert(TokenBucket {
+            tokens: limit.burst as f64,
+            updated: now,
+        });
+        bucket.refill(limit, now);
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return true;
+        }
+        false
+    }
+}
+
+impl<S> Layer<S> for RateLimitLayer {
+    type Service = RateLimitService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        RateLimitService {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RateLimitService<S> {
+    inner: S,
+    layer: RateLimitLayer,
+}
+
+impl<S, B> Service<Request<B>> for RateLimitService<S>
+where
+    S: Service<Request<B>, Response = Response>,
+    S::Future: Send + 'static,
+{
+    type Response = Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Response, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: Request<B>) -> Self::Future {
+        // requests without a known address (e.g. tests without a socket) are not limited
+        let address = request
+            .extensions()
+            .get::<ClientAddress>()
+            .map(|address| address.0);
+
+        if let Some(address) = address {
+            if !self.layer.acquire(address) {
+                return Box::pin(async { Ok(ResponseError::TooManyRequests.into_response()) });
+            }
+        }
+
+        Box::pin(self.inner.call(request))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::StatusCode;
+    use std::convert::Infallible;
+    use tower::ServiceExt;
+
+    fn request(address: &str) -> Request<Body> {
+        let mut request = Request::new(Body::empty());
+        request
+            .extensions_mut()
+            .insert(ClientAddress(address.parse().unwrap()));
+        request
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit() {
+        let layer = RateLimitLayer::new(Some(RateLimit::new(2, 1)));
+        let service = layer.layer(tower::service_fn(|_: Request<Body>| async {
+            Ok::<_, Infallible>(StatusCode::OK.into_response())
+        }));
+
+        // the burst is allowed
+        for _ in 0..2 {
+            let response = service.clone().oneshot(request("127.0.0.1")).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+        let response = service.clone().oneshot(request("127.0.0.1")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        // other addresses have their own bucket
+        let response = service.clone().oneshot(request("127.0.0.2")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_disabled() {
+        let layer = RateLimitLayer::new(None);
+        let service = layer.layer(tower::service_fn(|_: Request<Body>| async {
+            Ok::<_, Infallible>(StatusCode::OK.into_response())
+        }));
+
+        for _ in 0..10 {
+            let response = service.clone().oneshot(request("127.0.0.1")).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+    }
+}
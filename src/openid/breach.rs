@@ -0,0 +1,172 @@
+/*
+ * The MIT License (MIT)
+ *
+ * Copyright (c) 2022 Randoooom
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in the
+ * Software without restriction, including without limitation the rights to use, copy,
+ * modify, merge, publish, distribute, sublicense, and/or sell copies of the Software,
+ * and to permit persons to whom the Software is furnished to do so, subject to the
+ * following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+ * EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+ * MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+ * NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT
+ * HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR
+ * IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ *  SOFTWARE.
+ */
+
+use std::cmp::Ordering;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Seek, SeekFrom};
+use std::path::PathBuf;
+
+/// A local corpus of breached passwords as uppercase hex sha1 hashes (like the "Pwned Passwords"
+/// downloads), either as one file sorted by hash with `HASH[:COUNT]` lines or as a directory
+/// partitioned by the first five characters with `SUFFIX[:COUNT]` lines per file.
+pub struct BreachCorpus {
+    path: PathBuf,
+}
+
+impl BreachCorpus {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// Checks if the password is part of the corpus
+    pub fn contains(&self, password: &str) -> std::io::Result<bool> {
+        let hash = openssl::sha::sha1(password.as_bytes())
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect::<String>();
+
+        if self.path.is_dir() {
+            self.search_partition(hash.as_str())
+        } else {
+            self.search_sorted(hash.as_str())
+        }
+    }
+
+    /// Scan the file of the prefix for the suffix
+    fn search_partition(&self, hash: &str) -> std::io::Result<bool> {
+        let (prefix, suffix) = hash.split_at(5);
+        let path = [prefix.to_string(), format!("{}.txt", prefix)]
+            .into_iter()
+            .map(|name| self.path.join(name))
+            .find(|path| path.is_file());
+        let path = match path {
+            Some(path) => path,
+            None => return Ok(false),
+        };
+
+        for line in BufReader::new(File::open(path)?).lines() {
+            if key(line?.as_str()).eq_ignore_ascii_case(suffix) {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Binary search over the byte offsets of the sorted file
+    fn search_sorted(&self, hash: &str) -> std::io::Result<bool> {
+        let mut file = BufReader::new(File::open(self.path.as_path())?);
+        let (mut low, mut high) = (0, file.get_ref().metadata()?.len());
+
+        // the matching line starts within [low, high)
+        while low < high {
+            let middle = low + (high - low) / 2;
+            match line_from(&mut file, middle)? {
+                Some(line) => match key(line.as_str()).to_ascii_uppercase().as_str().cmp(hash) {
+                    Ordering::Equal => return Ok(true),
+                    Ordering::Less => low = middle + 1,
+                    Ordering::Greater => high = middle,
+                },
+                None => high = middle,
+            }
+        }
+        Ok(false)
+    }
+}
+
+/// The hash part of a line
+fn key(line: &str) -> &str {
+    line.split(':').next().unwrap_or_default().trim()
+}
+
+/// Read the first line starting at or after the offset
+fn line_from(file: &mut BufReader<File>, offset: u64) -> std::io::Result<Option<String>> {
+    let mut line = String::new();
+    if offset > 0 {
+        // skip the rest of the line the offset points into
+        file.seek(SeekFrom::Start(offset - 1))?;
+        file.read_line(&mut line)?;
+        line.clear();
+    } else {
+        file.seek(SeekFrom::Start(0))?;
+    }
+
+    match file.read_line(&mut line)? {
+        0 => Ok(None),
+        _ => Ok(Some(line)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hash(password: &str) -> String {
+        openssl::sha::sha1(password.as_bytes())
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect()
+    }
+
+    #[test]
+    fn test_sorted() {
+        let breached = ["password", "123456", "qwerty", "letmein", "dragon"];
+        let mut lines = breached
+            .iter()
+            .enumerate()
+            .map(|(count, password)| format!("{}:{}", hash(password), count + 1))
+            .collect::<Vec<String>>();
+        lines.sort();
+
+        let path = std::env::temp_dir().join(format!("breached-{}.txt", rbatis::Uuid::new()));
+        std::fs::write(&path, lines.join("\r\n")).unwrap();
+
+        let corpus = BreachCorpus::new(&path);
+        for password in breached {
+            assert!(corpus.contains(password).unwrap());
+        }
+        assert!(!corpus.contains("J*Wdtyfawdg*do0@e41").unwrap());
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_partitioned() {
+        let directory = std::env::temp_dir().join(format!("breached-{}", rbatis::Uuid::new()));
+        std::fs::create_dir(&directory).unwrap();
+        let hash = hash("password");
+        let (prefix, suffix) = hash.split_at(5);
+        std::fs::write(
+            directory.join(prefix),
+            format!("0000000000000000000000000000000000A:1\n{}:42\n", suffix),
+        )
+        .unwrap();
+
+        let corpus = BreachCorpus::new(&directory);
+        assert!(corpus.contains("password").unwrap());
+        assert!(!corpus.contains("J*Wdtyfawdg*do0@e41").unwrap());
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
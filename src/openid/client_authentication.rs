@@ -0,0 +1,224 @@
+/*
+ * The MIT License (MIT)
+ *
+ * Copyright (c) 2022 Randoooom
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in the
+ * Software without restriction, including without limitation the rights to use, copy,
+ * modify, merge, publish, distribute, sublicense, and/or sell copies of the Software,
+ * and to permit persons to whom the Software is furnished to do so, subject to the
+ * following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+ * EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+ * MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+ * NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT
+ * HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR
+ * IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ *  SOFTWARE.
+ */
+
+use crate::database::application::{Application, AuthMethod};
+use crate::error::ResponseError;
+use crate::locator::Locator;
+use crate::openid::mtls::ClientCertificate;
+use crate::ISSUER;
+use axum::http::header::AUTHORIZATION;
+use axum::http::HeaderMap;
+use chrono::{DateTime, Duration, Utc};
+use rbatis::Uuid;
+use rusty_paseto::prelude::*;
+use std::str::FromStr;
+
+/// The assertion type for client assertions signed as v4.public PASETO
+pub const CLIENT_ASSERTION_TYPE: &str =
+    "urn:ietf:params:oauth:client-assertion-type:paseto-v4-public";
+
+/// The maximum lifetime of a client assertion (in minutes)
+const ASSERTION_LIFETIME: i64 = 5;
+
+/// The client authentication parameters of the request body
+#[derive(Deserialize, Serialize, Default)]
+pub struct ClientCredentials {
+    pub client_id: Option<String>,
+    pub client_secret: Option<String>,
+    pub client_assertion_type: Option<String>,
+    pub client_assertion: Option<String>,
+}
+
+/// Authenticate the requesting application with exactly the method it is registered for
+pub async fn authenticate_application(
+    headers: &HeaderMap,
+    credentials: &ClientCredentials,
+    certificate: Option<&ClientCertificate>,
+    locator: &mut Locator,
+) -> Result<Application, ResponseError> {
+    // detect the used method
+    let basic = headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Basic "));
+    let (method, client_id, secret) = match (
+        basic,
+        &credentials.client_secret,
+        &credentials.client_assertion,
+    ) {
+        (Some(basic), None, None) => {
+            // decode client_id:client_secret
+            let decoded = base64::decode(basic)
+                .ok()
+                .and_then(|decoded| String::from_utf8(decoded).ok())
+                .ok_or(ResponseError::InvalidClient)?;
+            let (client_id, secret) = decoded
+                .split_once(':')
+                .ok_or(ResponseError::InvalidClient)?;
+
+            // both parts are form-urlencoded before joining them (RFC 6749 2.3.1)
+            (
+                AuthMethod::ClientSecretBasic,
+                form_urldecode(client_id).ok_or(ResponseError::InvalidClient)?,
+                Some(form_urldecode(secret).ok_or(ResponseError::InvalidClient)?),
+            )
+        }
+        (None, Some(secret), None) => (
+            AuthMethod::ClientSecretPost,
+            credentials
+                .client_id
+                .clone()
+                .ok_or(ResponseError::InvalidClient)?,
+            Some(secret.clone()),
+        ),
+        (None, None, Some(_)) => (
+            AuthMethod::PrivateKeyPaseto,
+            credentials
+                .client_id
+                .clone()
+                .ok_or(ResponseError::InvalidClient)?,
+            None,
+        ),
+        (None, None, None) => (
+            AuthMethod::None,
+            credentials
+                .client_id
+                .clone()
+                .ok_or(ResponseError::InvalidClient)?,
+            None,
+        ),
+        // more than one method is not allowed
+        _ => return Err(ResponseError::InvalidClient),
+    };
+
+    // get the application
+    let client_id = Uuid::from_str(client_id.as_str()).map_err(|_| ResponseError::InvalidClient)?;
+    let application = Application::from_client_id(&client_id, locator.connection())
+        .await
+        .unwrap()
+        .ok_or(ResponseError::InvalidClient)?;
+
+    // requests without secret or assertion may use the tls methods
+    let method = match (method, application.auth_method()) {
+        (AuthMethod::None, AuthMethod::TlsClientAuth) => AuthMethod::TlsClientAuth,
+        (AuthMethod::None, AuthMethod::SelfSignedTlsClientAuth) => {
+            AuthMethod::SelfSignedTlsClientAuth
+        }
+        (method, _) => method,
+    };
+    // only the registered method is allowed
+    if application.auth_method() != &method {
+        return Err(ResponseError::InvalidClient);
+    }
+
+    let authenticated = match method {
+        AuthMethod::ClientSecretBasic | AuthMethod::ClientSecretPost => {
+            application.verify_secret(secret.unwrap().as_str())
+        }
+        AuthMethod::PrivateKeyPaseto => verify_assertion(&application, credentials, locator),
+        AuthMethod::TlsClientAuth => certificate
+            .map(|certificate| {
+                certificate.verify_chain(locator.client_ca_path().as_deref())
+                    && application.tls_subject_dn().as_ref() == Some(&certificate.subject_dn())
+            })
+            .unwrap_or(false),
+        AuthMethod::SelfSignedTlsClientAuth => certificate
+            .map(|certificate| {
+                application.tls_thumbprint().as_ref() == Some(&certificate.thumbprint())
+            })
+            .unwrap_or(false),
+        AuthMethod::None => true,
+    };
+
+    match authenticated {
+        true => Ok(application),
+        false => Err(ResponseError::InvalidClient),
+    }
+}
+
+/// Decode an application/x-www-form-urlencoded value
+fn form_urldecode(value: &str) -> Option<String> {
+    percent_encoding::percent_decode_str(value.replace('+', " ").as_str())
+        .decode_utf8()
+        .ok()
+        .map(|decoded| decoded.to_string())
+}
+
+/// Verify the PASETO client assertion with the registered public key of the application
+fn verify_assertion(
+    application: &Application,
+    credentials: &ClientCredentials,
+    locator: &mut Locator,
+) -> bool {
+    if credentials.client_assertion_type.as_deref() != Some(CLIENT_ASSERTION_TYPE) {
+        return false;
+    }
+    let assertion = credentials.client_assertion.as_deref().unwrap();
+
+    // decode the registered key
+    let public_key = match application
+        .public_key()
+        .as_deref()
+        .and_then(|key| key.strip_prefix("k4.public."))
+        .and_then(|key| base64::decode_config(key, base64::URL_SAFE_NO_PAD).ok())
+    {
+        Some(key) if key.len() == 32 => Key::<32>::from(key.as_slice()),
+        _ => return false,
+    };
+    let public_key = PasetoAsymmetricPublicKey::<V4, Public>::from(&public_key);
+
+    // iss and sub have to be the client_id and aud the token endpoint
+    let client_id = application.client_id().to_string();
+    let audience = format!("{}/token", ISSUER.as_str());
+    let claims = match PasetoParser::<V4, Public>::default()
+        .check_claim(IssuerClaim::from(client_id.as_str()))
+        .check_claim(SubjectClaim::from(client_id.as_str()))
+        .check_claim(AudienceClaim::from(audience.as_str()))
+        .parse(assertion, &public_key)
+    {
+        Ok(claims) => claims,
+        Err(_) => return false,
+    };
+
+    // the assertion has to be short lived
+    let expiry = match claims["exp"]
+        .as_str()
+        .and_then(|exp| DateTime::parse_from_rfc3339(exp).ok())
+    {
+        Some(expiry) => expiry.with_timezone(&Utc),
+        None => return false,
+    };
+    if expiry > Utc::now() + Duration::minutes(ASSERTION_LIFETIME) {
+        return false;
+    }
+
+    // every assertion can only be used once
+    match claims["jti"].as_str() {
+        Some(jti) => locator
+            .replay_mut()
+            .check(format!("assertion:{}:{}", client_id, jti).as_str(), expiry),
+        None => false,
+    }
+}
@@ -0,0 +1,205 @@
+/*
+ * The MIT License (MIT)
+ *
+ * Copyright (c) 2022 Randoooom
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in the
+ * Software without restriction, including without limitation the rights to use, copy,
+ * modify, merge, publish, distribute, sublicense, and/or sell copies of the Software,
+ * and to permit persons to whom the Software is furnished to do so, subject to the
+ * following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+ * EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+ * MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+ * NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT
+ * HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR
+ * IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ *  SOFTWARE.
+ */
+
+use crate::locator::paseto::SigningKey;
+use crate::locator::replay::ReplayCache;
+use chrono::{DateTime, Duration, Utc};
+use rusty_paseto::prelude::*;
+
+/// The header carrying the proof
+pub const DPOP_HEADER: &str = "DPoP";
+
+/// The maximum age of a proof (in seconds)
+const PROOF_LIFETIME: i64 = 60;
+
+/// Calculate the ath claim (base64url encoded sha256 hash) of the access token
+pub fn access_token_hash(access_token: &str) -> String {
+    base64::encode_config(
+        openssl::sha::sha256(access_token.as_bytes()),
+        base64::URL_SAFE_NO_PAD,
+    )
+}
+
+/// Verify the proof of possession for the given request and return the PASERK pid of its key.
+///
+/// The proof is a v4.public PASETO signed by the client. Its footer carries the public key
+/// as PASERK (`{"paserk": "k4.public..."}`) and the claims have to contain jti, iat, htm, htu
+/// and ath (only when presented together with an access token).
+pub fn verify_proof(
+    proof: &str,
+    method: &str,
+    uri: &str,
+    access_token: Option<&str>,
+    replay: &mut ReplayCache,
+) -> Option<String> {
+    // the public key is embedded into the footer
+    let footer = proof.splitn(4, '.').nth(3)?;
+    let footer = base64::decode_config(footer, base64::URL_SAFE_NO_PAD).ok()?;
+    let footer = String::from_utf8(footer).ok()?;
+    let raw = serde_json::from_str::<serde_json::Value>(footer.as_str())
+        .ok()?
+        .get("paserk")?
+        .as_str()?
+        .strip_prefix("k4.public.")
+        .and_then(|key| base64::decode_config(key, base64::URL_SAFE_NO_PAD).ok())?;
+    if raw.len() != 32 {
+        return None;
+    }
+
+    // verify the signature
+    let key = Key::<32>::from(raw.as_slice());
+    let public_key = PasetoAsymmetricPublicKey::<V4, Public>::from(&key);
+    let claims = PasetoParser::<V4, Public>::default()
+        .set_footer(Footer::from(footer.as_str()))
+        .parse(proof, &public_key)
+        .ok()?;
+
+    // the proof has to be created for this request
+    if claims["htm"].as_str()? != method || claims["htu"].as_str()? != uri {
+        return None;
+    }
+    if let Some(access_token) = access_token {
+        if claims["ath"].as_str()? != access_token_hash(access_token) {
+            return None;
+        }
+    }
+
+    // only recent proofs are accepted
+    let issued = DateTime::parse_from_rfc3339(claims["iat"].as_str()?)
+        .ok()?
+        .with_timezone(&Utc);
+    let now = Utc::now();
+    if issued > now + Duration::seconds(PROOF_LIFETIME)
+        || issued < now - Duration::seconds(PROOF_LIFETIME)
+    {
+        return None;
+    }
+
+    // every proof can only be used once
+    let pid = SigningKey::calculate_pid(raw.as_slice());
+    let jti = claims["jti"].as_str()?;
+    if !replay.check(
+        format!("dpop:{}:{}", pid, jti).as_str(),
+        issued + Duration::seconds(PROOF_LIFETIME * 2),
+    ) {
+        return None;
+    }
+
+    Some(pid)
+}
+
+/// Checks if the cnf claim of the token is bound to the key of the given proof (if bound at all)
+pub fn proof_bound(
+    claims: &serde_json::Value,
+    proof: Option<&str>,
+    method: &str,
+    uri: &str,
+    access_token: &str,
+    replay: &mut ReplayCache,
+) -> bool {
+    match claims["cnf"]["kid"].as_str() {
+        Some(kid) => proof
+            .and_then(|proof| verify_proof(proof, method, uri, Some(access_token), replay))
+            .map(|pid| pid == kid)
+            .unwrap_or(false),
+        None => true,
+    }
+}
+
+/// Sign a proof with the given ed25519 key like a client would do
+#[cfg(test)]
+pub fn sign_proof(
+    pkey: &openssl::pkey::PKey<openssl::pkey::Private>,
+    method: &str,
+    uri: &str,
+    access_token: Option<&str>,
+) -> String {
+    let mut bytes = [0u8; 64];
+    bytes[..32].copy_from_slice(pkey.raw_private_key().unwrap().as_slice());
+    bytes[32..].copy_from_slice(pkey.raw_public_key().unwrap().as_slice());
+    let key = Key::<64>::from(bytes);
+    let private_key = PasetoAsymmetricPrivateKey::<V4, Public>::from(key.as_slice());
+
+    let paserk = format!(
+        "k4.public.{}",
+        base64::encode_config(pkey.raw_public_key().unwrap(), base64::URL_SAFE_NO_PAD)
+    );
+    let footer = json!({ "paserk": paserk }).to_string();
+    let jti = rbatis::Uuid::new().to_string();
+    let issued = Utc::now().to_rfc3339();
+
+    let mut builder = PasetoBuilder::<V4, Public>::default();
+    builder
+        .set_claim(TokenIdentifierClaim::from(jti.as_str()))
+        .set_claim(IssuedAtClaim::try_from(issued.as_str()).unwrap())
+        .set_claim(CustomClaim::try_from(("htm", method)).unwrap())
+        .set_claim(CustomClaim::try_from(("htu", uri)).unwrap())
+        .set_footer(Footer::from(footer.as_str()));
+    if let Some(access_token) = access_token {
+        builder.set_claim(CustomClaim::try_from(("ath", access_token_hash(access_token))).unwrap());
+    }
+
+    builder.build(&private_key).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::pkey::PKey;
+
+    #[test]
+    fn test_proof() {
+        let pkey = PKey::generate_ed25519().unwrap();
+        let mut replay = ReplayCache::new();
+        let uri = "https://example.com/token";
+
+        let proof = sign_proof(&pkey, "POST", uri, None);
+        let pid = verify_proof(proof.as_str(), "POST", uri, None, &mut replay).unwrap();
+        assert_eq!(
+            pid,
+            SigningKey::calculate_pid(pkey.raw_public_key().unwrap().as_slice())
+        );
+
+        // can not be replayed
+        assert!(verify_proof(proof.as_str(), "POST", uri, None, &mut replay).is_none());
+
+        // has to match the request
+        let proof = sign_proof(&pkey, "POST", uri, None);
+        assert!(verify_proof(proof.as_str(), "GET", uri, None, &mut replay).is_none());
+    }
+
+    #[test]
+    fn test_access_token_hash() {
+        let pkey = PKey::generate_ed25519().unwrap();
+        let mut replay = ReplayCache::new();
+        let uri = "https://example.com/userinfo";
+
+        let proof = sign_proof(&pkey, "GET", uri, Some("token"));
+        assert!(verify_proof(proof.as_str(), "GET", uri, Some("other"), &mut replay).is_none());
+
+        let proof = sign_proof(&pkey, "GET", uri, Some("token"));
+        assert!(verify_proof(proof.as_str(), "GET", uri, Some("token"), &mut replay).is_some());
+    }
+}
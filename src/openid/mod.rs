@@ -24,4 +24,8 @@
  */
 
 pub mod authorization;
+pub mod breach;
+pub mod client_authentication;
+pub mod dpop;
+pub mod mtls;
 pub mod verification;
@@ -0,0 +1,202 @@
+/*
+ * The MIT License (MIT)
+ *
+ * Copyright (c) 2022 Randoooom
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in the
+ * Software without restriction, including without limitation the rights to use, copy,
+ * modify, merge, publish, distribute, sublicense, and/or sell copies of the Software,
+ * and to permit persons to whom the Software is furnished to do so, subject to the
+ * following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+ * EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+ * MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+ * NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT
+ * HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR
+ * IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ *  SOFTWARE.
+ */
+
+use axum::http::HeaderMap;
+use openssl::stack::Stack;
+use openssl::x509::store::X509StoreBuilder;
+use openssl::x509::{X509StoreContext, X509};
+use std::net::IpAddr;
+
+/// The certificate presented by the client, either over the tls connection or a trusted proxy
+#[derive(Clone)]
+pub struct ClientCertificate(pub X509);
+
+impl ClientCertificate {
+    /// The x5t#S256 thumbprint (base64url encoded sha256 hash of the DER encoding)
+    pub fn thumbprint(&self) -> String {
+        let der = self.0.to_der().unwrap();
+        base64::encode_config(
+            openssl::sha::sha256(der.as_slice()),
+            base64::URL_SAFE_NO_PAD,
+        )
+    }
+
+    /// The subject distinguished name as string (RFC 4514 order)
+    pub fn subject_dn(&self) -> String {
+        let mut entries = self
+            .0
+            .subject_name()
+            .entries()
+            .map(|entry| {
+                format!(
+                    "{}={}",
+                    entry.object().nid().short_name().unwrap_or("UNKNOWN"),
+                    entry
+                        .data()
+                        .as_utf8()
+                        .map(|data| data.to_string())
+                        .unwrap_or_default()
+                )
+            })
+            .collect::<Vec<_>>();
+        // the most specific entry comes first
+        entries.reverse();
+
+        entries.join(",")
+    }
+
+    /// Verify the certificate chain against the configured client CAs
+    pub fn verify_chain(&self, ca_path: Option<&str>) -> bool {
+        // without CAs no certificate can be verified
+        let certificates = match ca_path
+            .and_then(|path| std::fs::read(path).ok())
+            .and_then(|pem| X509::stack_from_pem(pem.as_slice()).ok())
+        {
+            Some(certificates) => certificates,
+            None => return false,
+        };
+
+        // build the store
+        let mut store = X509StoreBuilder::new().unwrap();
+        for certificate in certificates {
+            store.add_cert(certificate).unwrap();
+        }
+        let store = store.build();
+
+        let chain = Stack::new().unwrap();
+        let mut context = X509StoreContext::new().unwrap();
+        context
+            .init(&store, &self.0, &chain, |context| context.verify_cert())
+            .unwrap_or(false)
+    }
+
+    /// Read the url encoded pem certificate forwarded by a trusted proxy
+    pub fn from_headers(
+        headers: &HeaderMap,
+        header: &str,
+        peer: Option<IpAddr>,
+        trusted: &[IpAddr],
+    ) -> Option<Self> {
+        // only trusted proxies may forward certificates
+        if !trusted.contains(&peer?) {
+            return None;
+        }
+
+        let value = headers.get(header)?.to_str().ok()?;
+        let pem = percent_encoding::percent_decode_str(value)
+            .decode_utf8()
+            .ok()?;
+        X509::from_pem(pem.as_bytes()).ok().map(Self)
+    }
+}
+
+/// Checks if the cnf claim of the token is bound to the given certificate (if bound at all)
+pub fn certificate_bound(
+    claims: &serde_json::Value,
+    certificate: Option<&ClientCertificate>,
+) -> bool {
+    match claims["cnf"]["x5t#S256"].as_str() {
+        Some(thumbprint) => certificate
+            .map(|certificate| certificate.thumbprint() == thumbprint)
+            .unwrap_or(false),
+        None => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::hash::MessageDigest;
+    use openssl::pkey::PKey;
+    use openssl::x509::X509NameBuilder;
+
+    /// Build a self signed certificate
+    fn certificate() -> X509 {
+        let pkey = PKey::generate_ed25519().unwrap();
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("O", "Example").unwrap();
+        name.append_entry_by_text("CN", "client").unwrap();
+        let name = name.build();
+
+        let mut builder = X509::builder().unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder.set_issuer_name(&name).unwrap();
+        builder.set_pubkey(&pkey).unwrap();
+        builder
+            .set_not_before(&openssl::asn1::Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        builder
+            .set_not_after(&openssl::asn1::Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        builder.sign(&pkey, MessageDigest::null()).unwrap();
+        builder.build()
+    }
+
+    #[test]
+    fn test_certificate() {
+        let certificate = ClientCertificate(certificate());
+
+        assert_eq!(certificate.subject_dn(), "CN=client,O=Example");
+        assert_eq!(certificate.thumbprint().len(), 43);
+
+        // bound tokens require the same certificate
+        let claims = json!({"cnf": {"x5t#S256": certificate.thumbprint()}});
+        assert!(certificate_bound(&claims, Some(&certificate)));
+        assert!(!certificate_bound(&claims, None));
+        assert!(!certificate_bound(
+            &claims,
+            Some(&ClientCertificate(self::certificate()))
+        ));
+        assert!(certificate_bound(&json!({}), None));
+    }
+
+    #[test]
+    fn test_forwarded_certificate() {
+        let pem = String::from_utf8(certificate().to_pem().unwrap()).unwrap();
+        let encoded =
+            percent_encoding::utf8_percent_encode(pem.as_str(), percent_encoding::NON_ALPHANUMERIC)
+                .to_string();
+
+        let mut headers = HeaderMap::new();
+        headers.insert("X-Client-Cert", encoded.parse().unwrap());
+        let proxy: IpAddr = "10.0.0.1".parse().unwrap();
+
+        // only accepted from trusted proxies
+        assert!(
+            ClientCertificate::from_headers(&headers, "X-Client-Cert", Some(proxy), &[proxy])
+                .is_some()
+        );
+        assert!(ClientCertificate::from_headers(
+            &headers,
+            "X-Client-Cert",
+            Some("10.0.0.2".parse().unwrap()),
+            &[proxy]
+        )
+        .is_none());
+        assert!(
+            ClientCertificate::from_headers(&headers, "X-Client-Cert", None, &[proxy]).is_none()
+        );
+    }
+}
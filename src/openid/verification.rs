@@ -23,20 +23,83 @@
  *  SOFTWARE.
  */
 
+use crate::openid::breach::BreachCorpus;
+use crate::{
+    BREACHED_PASSWORDS_PATH, PASSWORD_MAX_LENGTH, PASSWORD_MIN_LENGTH, PASSWORD_MIN_SCORE,
+};
+
+/// Why a password was rejected, including the feedback of zxcvbn
+#[derive(Clone, Debug, Serialize)]
+pub struct PasswordFeedback {
+    error: String,
+    warning: Option<String>,
+    suggestions: Vec<String>,
+}
+
+impl PasswordFeedback {
+    fn new(error: impl Into<String>) -> Self {
+        Self {
+            error: error.into(),
+            warning: None,
+            suggestions: Vec::new(),
+        }
+    }
+}
+
 pub struct Verification;
 
 impl Verification {
-    /// Verify the strength of the given password.
-    /// Only returns true if the estimated strength is greater than or equal strong
-    pub fn password_strong_enough(password: &str) -> bool {
+    /// Verify the length and the strength of the given password against the configured policy.
+    /// The user inputs (like names and email) are considered as known to an attacker.
+    pub fn password_strong_enough(
+        password: &str,
+        user_inputs: &[&str],
+    ) -> Result<(), PasswordFeedback> {
+        let length = password.chars().count();
+        if length < *PASSWORD_MIN_LENGTH {
+            return Err(PasswordFeedback::new(format!(
+                "Password too short (at least {} characters)",
+                *PASSWORD_MIN_LENGTH
+            )));
+        }
+        if length > *PASSWORD_MAX_LENGTH {
+            return Err(PasswordFeedback::new(format!(
+                "Password too long (at most {} characters)",
+                *PASSWORD_MAX_LENGTH
+            )));
+        }
+
         // analysis with zxcvbn
-        let result = zxcvbn::zxcvbn(password, &[]);
+        let result = zxcvbn::zxcvbn(password, user_inputs)
+            .map_err(|_| PasswordFeedback::new("Password not strong enough"))?;
+        if result.score() >= *PASSWORD_MIN_SCORE {
+            return Ok(());
+        }
 
-        // handle errors
-        match result {
-            // only allow at least strong password
-            Ok(result) => result.score() >= 3,
-            Err(_) => false,
+        // explain the rejection
+        let mut feedback = PasswordFeedback::new("Password not strong enough");
+        if let Some(analysis) = result.feedback() {
+            feedback.warning = analysis.warning().map(|warning| warning.to_string());
+            feedback.suggestions = analysis
+                .suggestions()
+                .iter()
+                .map(|suggestion| suggestion.to_string())
+                .collect();
+        }
+        Err(feedback)
+    }
+
+    /// Checks the password against the local corpus of breached passwords (if configured)
+    pub fn password_breached(password: &str) -> bool {
+        match BREACHED_PASSWORDS_PATH.as_deref() {
+            Some(path) => BreachCorpus::new(path)
+                .contains(password)
+                .unwrap_or_else(|error| {
+                    // do not block the users because of a broken corpus
+                    error!("Failed to search the breached passwords: {}", error);
+                    false
+                }),
+            None => false,
         }
     }
 
@@ -57,11 +120,29 @@ mod tests {
     #[test]
     fn test_password_strength() {
         let invalid = "12345";
-        assert!(!Verification::password_strong_enough(invalid));
+        assert!(Verification::password_strong_enough(invalid, &[]).is_err());
 
         // these are just random typed chars on the keyboard
         let valid = "J*Wdtyfawdg*do0@e41";
-        assert!(Verification::password_strong_enough(valid));
+        assert!(Verification::password_strong_enough(valid, &[]).is_ok());
+    }
+
+    #[test]
+    fn test_password_feedback() {
+        let feedback = Verification::password_strong_enough("password", &[]).unwrap_err();
+        assert!(feedback.warning.is_some());
+        assert!(!feedback.suggestions.is_empty());
+
+        let feedback = Verification::password_strong_enough("J*W", &[]).unwrap_err();
+        assert!(feedback.error.contains("too short"));
+    }
+
+    #[test]
+    fn test_password_user_inputs() {
+        let password = "xkq7vbz93mwp";
+        assert!(Verification::password_strong_enough(password, &[]).is_ok());
+        // known to an attacker
+        assert!(Verification::password_strong_enough(password, &["xkq7vbz93mwp"]).is_err());
     }
 
     #[test]
@@ -0,0 +1,116 @@
+/*
+ * The MIT License (MIT)
+ *
+ * Copyright (c) 2022 Randoooom
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in the
+ * Software without restriction, including without limitation the rights to use, copy,
+ * modify, merge, publish, distribute, sublicense, and/or sell copies of the Software,
+ * and to permit persons to whom the Software is furnished to do so, subject to the
+ * following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+ * EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+ * MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+ * NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT
+ * HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR
+ * IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ *  SOFTWARE.
+ */
+
+use crate::database::application::{Application, AuthMethod};
+use crate::error::ResponseError;
+use crate::locator::LocatorPointer;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::{Extension, Json};
+use rbatis::crud::CRUD;
+
+#[derive(Deserialize, Serialize)]
+pub struct RegisterApplication {
+    /// The displayable name
+    name: String,
+    /// The registered redirect uri
+    redirect_uri: String,
+    /// The method used at the token endpoint
+    auth_method: AuthMethod,
+    /// The ed25519 public key as PASERK (k4.public), required for private_key_paseto
+    public_key: Option<String>,
+    /// The subject DN of the client certificate, required for tls_client_auth
+    tls_subject_dn: Option<String>,
+    /// The x5t#S256 thumbprint, required for self_signed_tls_client_auth
+    tls_thumbprint: Option<String>,
+}
+
+pub async fn post_application(
+    Extension(locator): Extension<LocatorPointer>,
+    Json(data): Json<RegisterApplication>,
+) -> impl IntoResponse {
+    // only the secret based methods get a secret
+    let secret = match data.auth_method {
+        AuthMethod::ClientSecretBasic | AuthMethod::ClientSecretPost => {
+            Some(Application::gen_secret())
+        }
+        AuthMethod::PrivateKeyPaseto => {
+            // the key is required for verifying the assertions
+            if !data
+                .public_key
+                .as_deref()
+                .map(|key| key.starts_with("k4.public."))
+                .unwrap_or(false)
+            {
+                return Err(ResponseError::BadRequest("Public key not valid".into()));
+            }
+            None
+        }
+        AuthMethod::TlsClientAuth => {
+            if data.tls_subject_dn.is_none() {
+                return Err(ResponseError::BadRequest("Subject DN required".into()));
+            }
+            None
+        }
+        AuthMethod::SelfSignedTlsClientAuth => {
+            if data.tls_thumbprint.is_none() {
+                return Err(ResponseError::BadRequest("Thumbprint required".into()));
+            }
+            None
+        }
+        AuthMethod::None => None,
+    };
+
+    // build the application
+    let application = Application::builder()
+        .name(data.name)
+        .redirect_uri(data.redirect_uri)
+        .client_secret(secret.clone())
+        .auth_method(data.auth_method)
+        .public_key(data.public_key)
+        .tls_subject_dn(data.tls_subject_dn)
+        .tls_thumbprint(data.tls_thumbprint)
+        .build();
+
+    // lock the locator
+    let locked = locator.lock().await;
+    // save it
+    locked.connection().save(&application, &[]).await.unwrap();
+
+    // the plain secret is only returned once
+    Ok((
+        StatusCode::CREATED,
+        Json(json!({
+            "client_id": application.client_id(),
+            "client_secret": secret,
+            "name": application.name(),
+            "redirect_uri": application.redirect_uri(),
+            "auth_method": application.auth_method(),
+            "public_key": application.public_key(),
+            "tls_subject_dn": application.tls_subject_dn(),
+            "tls_thumbprint": application.tls_thumbprint(),
+        })),
+    ))
+}
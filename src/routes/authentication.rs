@@ -23,33 +23,54 @@
  *  SOFTWARE.
  */
 
+use crate::database::attempts::LoginAttempts;
 use crate::database::client::{
-    hash_password, Client, ClientAuthenticationData, ClientVerificationToken, Gender,
+    hash_password, Client, ClientAuthenticationData, ClientVerificationToken, Gender, TokenPurpose,
 };
+use crate::database::credential::WebauthnCredential;
+use crate::database::outbox::OutgoingMail;
+use crate::database::recovery::RecoveryCode;
 use crate::error::ResponseError;
 use crate::locator::mail::MailOptions;
-use crate::locator::LocatorPointer;
-use crate::middleware::SessionId;
+use crate::locator::template::MailTemplate;
+use crate::locator::webauthn::AuthenticationCredential;
+use crate::locator::{Locator, LocatorPointer};
+use crate::middleware::{ClientAddress, SessionId};
 use crate::openid::verification::Verification;
-use crate::ROOT;
-use axum::http::StatusCode;
+use crate::routes::send_security_notice;
+use crate::routes::webauthn::verify_second_factor;
+use crate::{
+    LOGIN_LOCK_DURATION, LOGIN_REQUIRE_VERIFIED_EMAIL, ROOT, VERIFICATION_RESEND_INTERVAL,
+};
+use axum::http::header::USER_AGENT;
+use axum::http::{HeaderMap, StatusCode};
 use axum::response::IntoResponse;
 use axum::{Extension, Json};
+use chrono::{Duration, Utc};
 use rbatis::crud::CRUD;
+use rbatis::rbatis::Rbatis;
+use rbatis::{TimestampZ, Uuid};
+use std::str::FromStr;
 
 #[derive(Deserialize, Serialize, TypedBuilder)]
 pub struct AuthenticationRequest {
-    /// the username / nickname of the client
-    nickname: String,
+    /// the nickname or the email of the client
+    #[serde(alias = "nickname")]
+    identifier: String,
     /// the password
     password: String,
     /// the totp
     token: Option<String>,
+    /// the assertion of a registered authenticator (in place of the totp)
+    #[builder(default)]
+    webauthn: Option<AuthenticationCredential>,
 }
 
 pub async fn post_login(
     Json(data): Json<AuthenticationRequest>,
     Extension(locator): Extension<LocatorPointer>,
+    address: Option<Extension<ClientAddress>>,
+    headers: HeaderMap,
 ) -> impl IntoResponse {
     // lock the locator
     let mut locked = locator.lock().await;
@@ -57,28 +78,268 @@ pub async fn post_login(
     let connection = locked.connection();
 
     // get the client
-    let client = Client::from_nickname(data.nickname.as_str(), &connection)
+    let client = Client::from_identifier(data.identifier.as_str(), &connection)
         .await
-        .unwrap();
-    if let Some(client) = client {
+        .unwrap()
+        // the email may only be used once it is verified
+        .filter(|(client, email)| {
+            !*email || !*LOGIN_REQUIRE_VERIFIED_EMAIL || *client.email_verified()
+        })
+        .map(|(client, _)| client);
+
+    // the failed attempts are tracked per source and per account
+    let keys = attempt_keys(
+        client.as_ref(),
+        address.as_ref().map(|Extension(address)| address),
+    );
+    check_locks(keys.as_slice(), connection).await?;
+
+    if let Some(client) = &client {
         // get the auth data
         let authentication_data = client.authentication_data(&connection).await.unwrap();
 
-        if let Some(authentication_data) = authentication_data {
-            // authenticate
-            if authentication_data.login(data.password.as_str(), data.token.as_deref()) {
+        if let Some(mut authentication_data) = authentication_data {
+            // registered authenticators are a second factor like the totp
+            let credentials = WebauthnCredential::of_client(client.sub(), connection)
+                .await
+                .unwrap();
+
+            // authenticate and record the used methods
+            let amr = if let Some(assertion) = &data.webauthn {
+                // an authenticator may be used in place of the totp
+                let verified = authentication_data.verify_password(data.password.as_str())
+                    && verify_second_factor(assertion, credentials, &mut locked).await;
+                verified.then(|| vec!["pwd".to_string(), "hwk".to_string()])
+            } else if authentication_data.login(data.password.as_str(), data.token.as_deref()) {
+                if *authentication_data.totp() {
+                    // persist the used totp step
+                    locked
+                        .connection()
+                        .update_by_column("uuid", &authentication_data)
+                        .await
+                        .unwrap();
+                    Some(vec!["pwd".to_string(), "otp".to_string()])
+                } else if credentials.is_empty() {
+                    Some(vec!["pwd".to_string()])
+                } else {
+                    // the password alone is not enough once an authenticator is registered,
+                    // only now the authenticators of the client are revealed
+                    let options = locked
+                        .webauthn_mut()
+                        .request_options(Some(client.sub().clone()), credentials.as_slice());
+                    return Ok((
+                        StatusCode::UNAUTHORIZED,
+                        Json(json!({"error": "second_factor_required", "webauthn": options})),
+                    ));
+                }
+            } else if let (true, Some(token)) = (*authentication_data.totp(), data.token.as_deref())
+            {
+                // a recovery code may be used in place of the totp (it is a one-time password as well)
+                let redeemed = authentication_data.verify_password(data.password.as_str())
+                    && RecoveryCode::redeem(client.sub(), token, locked.connection())
+                        .await
+                        .unwrap();
+                redeemed.then(|| vec!["pwd".to_string(), "otp".to_string()])
+            } else {
+                None
+            };
+
+            if let Some(amr) = amr {
+                // migrate outdated hashes transparently
+                if authentication_data.upgrade_hash(data.password.as_str()) {
+                    locked
+                        .connection()
+                        .update_by_column("uuid", &authentication_data)
+                        .await
+                        .unwrap();
+                }
+
+                // forget the failures of the account
+                record_success(client, locked.connection()).await;
+
                 // start the session
-                let session = locked.auth_mut().start_session(client.sub().clone());
+                let session = locked.auth_mut().start_session(client.sub().clone(), amr);
+                notify_login(client, address, &headers, &locked).await;
                 // return the session
                 return Ok((StatusCode::OK, Json(json!({ "session_id": session }))));
             }
         }
     }
 
+    // record the failure
+    record_failure(keys, client.as_ref(), &locked).await;
+
     // return 401
     Err(ResponseError::Unauthorized)
 }
 
+/// The keys the failed logins are tracked by, per source address and per account
+pub fn attempt_keys(client: Option<&Client>, address: Option<&ClientAddress>) -> Vec<String> {
+    let mut keys = Vec::new();
+    if let Some(address) = address {
+        keys.push(LoginAttempts::address_key(&address.0));
+    }
+    if let Some(client) = client {
+        keys.push(LoginAttempts::client_key(client.sub()));
+    }
+
+    keys
+}
+
+/// Refuse the login while one of the keys is locked
+pub async fn check_locks(keys: &[String], connection: &Rbatis) -> Result<(), ResponseError> {
+    for key in keys.iter() {
+        if let Some(attempts) = LoginAttempts::get(key, connection).await.unwrap() {
+            if let Some(seconds) = attempts.retry_after() {
+                return Err(ResponseError::LoginLocked(seconds));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Record the failed login for all keys
+pub async fn record_failure(keys: Vec<String>, client: Option<&Client>, locator: &Locator) {
+    for key in keys {
+        let (attempts, locked_now) = LoginAttempts::fail(key, locator.connection())
+            .await
+            .unwrap();
+
+        // the owner can unlock the account by mail
+        if let (true, Some(client)) = (locked_now, client) {
+            if attempts.key() == &LoginAttempts::client_key(client.sub()) {
+                send_unlock_mail(client, locator).await;
+            }
+        }
+    }
+}
+
+/// Forget the failed logins of the account
+pub async fn record_success(client: &Client, connection: &Rbatis) {
+    LoginAttempts::reset(&LoginAttempts::client_key(client.sub()), connection)
+        .await
+        .unwrap();
+}
+
+/// Send the link to unlock the account
+async fn send_unlock_mail(client: &Client, locator: &Locator) {
+    let connection = locator.connection();
+
+    // only the latest link is valid
+    for token in client
+        .verification_tokens(TokenPurpose::AccountUnlock, connection)
+        .await
+        .unwrap()
+    {
+        connection
+            .remove_by_column::<ClientVerificationToken, _>("uuid", token.uuid())
+            .await
+            .unwrap();
+    }
+
+    let token = ClientVerificationToken::new(client.sub().clone(), TokenPurpose::AccountUnlock);
+    connection.save(&token, &[]).await.unwrap();
+
+    let link = format!("{}/unlock?token={}", ROOT.as_str(), token.uuid());
+    let minutes = LOGIN_LOCK_DURATION.to_string();
+    let mail = locator.templates().render(
+        MailTemplate::AccountLocked,
+        client.locale(),
+        &[
+            ("name", client.preferred_username().as_str()),
+            ("minutes", minutes.as_str()),
+            ("link", link.as_str()),
+        ],
+    );
+    let mail = MailOptions::from_template(client.email().clone(), mail);
+    OutgoingMail::queue(mail, connection).await.unwrap();
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct UnlockAccount {
+    /// the token from the unlock mail
+    token: String,
+}
+
+pub async fn post_unlock(
+    Extension(locator): Extension<LocatorPointer>,
+    Json(data): Json<UnlockAccount>,
+) -> impl IntoResponse {
+    // lock the locator
+    let locked = locator.lock().await;
+    let connection = locked.connection();
+
+    // redeem the token
+    let token = match Uuid::from_str(data.token.as_str()) {
+        Ok(uuid) => ClientVerificationToken::redeem(&uuid, TokenPurpose::AccountUnlock, connection)
+            .await
+            .unwrap(),
+        Err(_) => None,
+    }
+    .ok_or_else(|| ResponseError::BadRequest("Invalid token".into()))?;
+
+    LoginAttempts::reset(&LoginAttempts::client_key(token.client()), connection)
+        .await
+        .unwrap();
+
+    Ok((StatusCode::OK, Json(json!({"message": "Unlocked"}))))
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct AdminUnlock {
+    /// the nickname or email of the client
+    identifier: String,
+}
+
+pub async fn post_admin_unlock(
+    Extension(locator): Extension<LocatorPointer>,
+    Json(data): Json<AdminUnlock>,
+) -> impl IntoResponse {
+    // lock the locator
+    let locked = locator.lock().await;
+    let connection = locked.connection();
+
+    let (client, _) = Client::from_identifier(data.identifier.as_str(), connection)
+        .await
+        .unwrap()
+        .ok_or_else(|| ResponseError::BadRequest("Unknown client".into()))?;
+    LoginAttempts::reset(&LoginAttempts::client_key(client.sub()), connection)
+        .await
+        .unwrap();
+
+    Ok((StatusCode::OK, Json(json!({"message": "Unlocked"}))))
+}
+
+/// Notify the client about the new login
+pub async fn notify_login(
+    client: &Client,
+    address: Option<Extension<ClientAddress>>,
+    headers: &HeaderMap,
+    locator: &Locator,
+) {
+    let ip = address
+        .map(|Extension(address)| address.0.to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+    let user_agent = headers
+        .get(USER_AGENT)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("unknown");
+    let time = Utc::now().format("%Y-%m-%d %H:%M UTC").to_string();
+
+    send_security_notice(
+        client,
+        MailTemplate::NewLogin,
+        &[
+            ("ip", ip.as_str()),
+            ("user_agent", user_agent),
+            ("time", time.as_str()),
+        ],
+        locator,
+    )
+    .await;
+}
+
 #[derive(Deserialize, Serialize)]
 pub struct SignupRequest {
     /// The full name in displayable form
@@ -122,12 +383,6 @@ pub async fn post_signup(
     // lock the locator
     let locked = locator.lock().await;
 
-    // verify the strength of the password
-    if !Verification::password_strong_enough(data.password.as_str()) {
-        return Err(ResponseError::BadRequest(
-            "Password not strong enough".into(),
-        ));
-    }
     // validate the email (check via regex)
     if !Verification::email_valid(data.email.as_str()) {
         return Err(ResponseError::BadRequest("Email not valid".into()));
@@ -152,6 +407,15 @@ pub async fn post_signup(
         .zoneinfo(&data.zoneinfo)
         .build();
 
+    // verify the strength of the password
+    Verification::password_strong_enough(data.password.as_str(), &client.user_inputs())
+        .map_err(ResponseError::WeakPassword)?;
+    if Verification::password_breached(data.password.as_str()) {
+        return Err(ResponseError::BadRequest(
+            "Password found in a data breach".into(),
+        ));
+    }
+
     // build the authentication data
     let auth_data = ClientAuthenticationData::builder()
         .client(client.sub().clone())
@@ -165,34 +429,113 @@ pub async fn post_signup(
     connection.save(&auth_data, &[]).await.unwrap();
 
     // send the email verification
-    let _ = {
-        // setup the verification token
-        let token = ClientVerificationToken::builder()
-            .client(client.sub().clone())
-            .build();
-        // save it
-        connection.save(&token, &[]).await.unwrap();
-
-        // setup the mail
-        let mail = MailOptions::builder()
-            .subject("E-Mail Verification".to_string())
-            .to(client.email().clone())
-            .content(
-                format!(
-                    "Hey {name}!</br>Please click <a href={root}/verify_email?token={token}>here</a> to verify your E-Mail!",
-                    name = client.preferred_username(),
-                    root = ROOT.as_str(),
-                    token = token.uuid()
-                )
-            )
-            .build();
-        // send it
-        locked.mail().send(mail).await.unwrap();
-    };
+    send_verification_mail(&client, &locked).await;
 
     Ok((StatusCode::CREATED, Json(json!(client))))
 }
 
+/// Create a new email verification token for the client and send it
+async fn send_verification_mail(client: &Client, locator: &Locator) {
+    // setup the verification token
+    let token = ClientVerificationToken::new(client.sub().clone(), TokenPurpose::EmailVerification);
+    // save it
+    locator.connection().save(&token, &[]).await.unwrap();
+
+    // setup the mail
+    let link = format!("{}/verify_email?token={}", ROOT.as_str(), token.uuid());
+    let mail = locator.templates().render(
+        MailTemplate::EmailVerification,
+        client.locale(),
+        &[
+            ("name", client.preferred_username().as_str()),
+            ("link", link.as_str()),
+        ],
+    );
+    let mail = MailOptions::from_template(client.email().clone(), mail);
+    // queue it
+    OutgoingMail::queue(mail, locator.connection())
+        .await
+        .unwrap();
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct VerifyEmail {
+    /// the token from the verification mail
+    token: String,
+}
+
+pub async fn post_verify_email(
+    Extension(locator): Extension<LocatorPointer>,
+    Json(data): Json<VerifyEmail>,
+) -> impl IntoResponse {
+    // lock the locator
+    let locked = locator.lock().await;
+    let connection = locked.connection();
+
+    // redeem the token
+    let token = match Uuid::from_str(data.token.as_str()) {
+        Ok(uuid) => {
+            ClientVerificationToken::redeem(&uuid, TokenPurpose::EmailVerification, connection)
+                .await
+                .unwrap()
+        }
+        Err(_) => None,
+    }
+    .ok_or_else(|| ResponseError::BadRequest("Invalid token".into()))?;
+
+    // get the client
+    let client: Option<Client> = connection
+        .fetch_by_column("sub", token.client())
+        .await
+        .unwrap();
+    let mut client = client.ok_or_else(|| ResponseError::BadRequest("Invalid token".into()))?;
+
+    // update
+    client
+        .set_email_verified(true)
+        .set_updated_at(TimestampZ::now());
+    connection.update_by_column("sub", &client).await.unwrap();
+
+    Ok((StatusCode::OK, Json(json!({"message": "Verified"}))))
+}
+
+pub async fn post_resend_verification(
+    Extension(locator): Extension<LocatorPointer>,
+    Extension(client): Extension<Client>,
+) -> impl IntoResponse {
+    if *client.email_verified() {
+        return Err(ResponseError::BadRequest("Email already verified".into()));
+    }
+
+    // lock the locator
+    let locked = locator.lock().await;
+    let connection = locked.connection();
+
+    // limit how often the mail can be sent
+    let tokens = client
+        .verification_tokens(TokenPurpose::EmailVerification, connection)
+        .await
+        .unwrap();
+    let interval = Duration::minutes(*VERIFICATION_RESEND_INTERVAL);
+    if tokens
+        .iter()
+        .any(|token| token.created_at().inner + interval > Utc::now())
+    {
+        return Err(ResponseError::TooManyRequests);
+    }
+
+    // invalidate the old tokens
+    for token in tokens {
+        connection
+            .remove_by_column::<ClientVerificationToken, _>("uuid", token.uuid())
+            .await
+            .unwrap();
+    }
+    send_verification_mail(&client, &locked).await;
+
+    Ok((StatusCode::OK, Json(json!({"message": "Sent"}))))
+}
+
 pub async fn post_logout(
     Extension(locator): Extension<LocatorPointer>,
     Extension(session_id): Extension<SessionId>,
@@ -216,13 +559,14 @@ pub async fn put_password(
     Extension(client): Extension<Client>,
     Json(update): Json<UpdatePassword>,
     Extension(session_id): Extension<SessionId>,
-) -> impl IntoResponse {
+) -> Result<impl IntoResponse, ResponseError> {
     // verify the strength of the password
-    if !Verification::password_strong_enough(update.password.as_str()) {
-        return (
-            StatusCode::BAD_REQUEST,
-            Json(json!({"error": "password not strong enough"})),
-        );
+    Verification::password_strong_enough(update.password.as_str(), &client.user_inputs())
+        .map_err(ResponseError::WeakPassword)?;
+    if Verification::password_breached(update.password.as_str()) {
+        return Err(ResponseError::BadRequest(
+            "Password found in a data breach".into(),
+        ));
     }
 
     // lock
@@ -243,104 +587,416 @@ pub async fn put_password(
 
     // end the session
     locator.auth_mut().end_session(session_id.0.as_str());
+    send_security_notice(&client, MailTemplate::PasswordChanged, &[], &locator).await;
 
-    (
+    Ok((
         StatusCode::OK,
         Json(json!({"message": "Changed password. Session canceled."})),
-    )
+    ))
 }
 
 #[derive(Deserialize, Serialize)]
-pub struct ActivateTOTP {
-    token: String,
+pub struct RequestPasswordReset {
+    /// the nickname or email of the client
+    identifier: String,
 }
 
-pub async fn post_activate_totp(
+pub async fn post_request_password_reset(
     Extension(locator): Extension<LocatorPointer>,
-    Extension(client): Extension<Client>,
-    Json(activate): Json<ActivateTOTP>,
+    Json(data): Json<RequestPasswordReset>,
 ) -> impl IntoResponse {
     // lock the locator
-    let locator = locator.lock().await;
-    let connection = locator.connection();
+    let locked = locator.lock().await;
+    let connection = locked.connection();
 
-    // get the auth data
-    let mut auth = client
-        .authentication_data(connection)
+    // get the client
+    let client = Client::from_identifier(data.identifier.as_str(), connection)
         .await
         .unwrap()
-        .unwrap();
-    // verify the token
-    if !auth.validate_totp(activate.token.as_str()) {
-        return (
-            StatusCode::UNAUTHORIZED,
-            Json(json!({"error": "Invalid token"})),
+        .map(|(client, _)| client);
+
+    if let Some(client) = client {
+        // invalidate the previous tokens
+        for token in client
+            .verification_tokens(TokenPurpose::PasswordReset, connection)
+            .await
+            .unwrap()
+        {
+            connection
+                .remove_by_column::<ClientVerificationToken, _>("uuid", token.uuid())
+                .await
+                .unwrap();
+        }
+
+        // setup the token
+        let token = ClientVerificationToken::new(client.sub().clone(), TokenPurpose::PasswordReset);
+        connection.save(&token, &[]).await.unwrap();
+
+        // setup the mail
+        let link = format!("{}/reset_password?token={}", ROOT.as_str(), token.uuid());
+        let mail = locked.templates().render(
+            MailTemplate::PasswordReset,
+            client.locale(),
+            &[
+                ("name", client.preferred_username().as_str()),
+                ("link", link.as_str()),
+            ],
         );
+        let mail = MailOptions::from_template(client.email().clone(), mail);
+
+        // queue it, the delivery happens in the background
+        OutgoingMail::queue(mail, connection).await.unwrap();
     }
 
-    // update
-    auth.set_totp(true);
-    connection.update_by_column("uuid", &auth).await.unwrap();
+    // always answer the same way
+    (
+        StatusCode::OK,
+        Json(json!({"message": "If the account exists, a mail has been sent"})),
+    )
+}
 
-    (StatusCode::OK, Json(json!({"message": "Enabled"})))
+#[derive(Deserialize, Serialize)]
+pub struct ResetPassword {
+    /// the token from the reset mail
+    token: String,
+    /// the new password
+    password: String,
 }
 
-pub async fn post_disable_totp(
+pub async fn post_reset_password(
     Extension(locator): Extension<LocatorPointer>,
-    Extension(client): Extension<Client>,
-    Json(verification): Json<ActivateTOTP>,
+    Json(data): Json<ResetPassword>,
 ) -> impl IntoResponse {
     // lock the locator
-    let locator = locator.lock().await;
-    let connection = locator.connection();
-    // get the auth data
-    let mut authentication_data = client
-        .authentication_data(&connection)
-        .await
-        .unwrap()
-        .unwrap();
+    let mut locked = locator.lock().await;
+    let connection = locked.connection();
 
-    // verify the token
-    if !authentication_data.validate_totp(verification.token.as_str()) {
-        return (
-            StatusCode::UNAUTHORIZED,
-            Json(json!({"error": "Invalid token"})),
-        );
+    // the token is only redeemed once the password is accepted
+    let uuid = Uuid::from_str(data.token.as_str())
+        .map_err(|_| ResponseError::BadRequest("Invalid token".into()))?;
+    let token: Option<ClientVerificationToken> =
+        connection.fetch_by_column("uuid", &uuid).await.unwrap();
+    let client: Option<Client> = match token
+        .filter(|token| token.purpose() == &TokenPurpose::PasswordReset && token.is_active())
+    {
+        Some(token) => connection
+            .fetch_by_column("sub", token.client())
+            .await
+            .unwrap(),
+        None => None,
+    };
+    let client = client.ok_or_else(|| ResponseError::BadRequest("Invalid token".into()))?;
+
+    // verify the strength of the password
+    Verification::password_strong_enough(data.password.as_str(), &client.user_inputs())
+        .map_err(ResponseError::WeakPassword)?;
+    if Verification::password_breached(data.password.as_str()) {
+        return Err(ResponseError::BadRequest(
+            "Password found in a data breach".into(),
+        ));
     }
 
-    // regenerate the secret
-    authentication_data.set_secret(ClientAuthenticationData::gen_secret());
-    authentication_data.set_totp(false);
-    // update
-    connection
-        .update_by_column("uuid", &authentication_data)
+    // redeem the token
+    let token = ClientVerificationToken::redeem(&uuid, TokenPurpose::PasswordReset, connection)
+        .await
+        .unwrap()
+        .ok_or_else(|| ResponseError::BadRequest("Invalid token".into()))?;
+
+    // get the auth data of the client
+    let auth: Option<ClientAuthenticationData> = connection
+        .fetch_by_column("client", token.client())
         .await
         .unwrap();
+    let mut auth = auth.ok_or_else(|| ResponseError::BadRequest("Invalid token".into()))?;
 
-    (StatusCode::OK, Json(json!({"message": "Disabled"})))
+    // update the password
+    auth.set_password(hash_password(data.password));
+    connection.update_by_column("uuid", &auth).await.unwrap();
+
+    // end all sessions
+    locked.auth_mut().end_sessions(token.client());
+
+    // notify the client
+    send_security_notice(&client, MailTemplate::PasswordChanged, &[], &locked).await;
+
+    Ok((
+        StatusCode::OK,
+        Json(json!({"message": "Changed password. Sessions canceled."})),
+    ))
 }
 
-pub async fn get_qr_code(
-    Extension(client): Extension<Client>,
+#[derive(Deserialize, Serialize)]
+pub struct RequestMagicLink {
+    /// the nickname or email of the client
+    identifier: String,
+}
+
+pub async fn post_request_magic_link(
     Extension(locator): Extension<LocatorPointer>,
+    Json(data): Json<RequestMagicLink>,
 ) -> impl IntoResponse {
-    // lock
-    let locator = locator.lock().await;
-    let connection = locator.connection();
+    // lock the locator
+    let locked = locator.lock().await;
+    let connection = locked.connection();
 
-    // get the auth data
-    let authentication_data = client
-        .authentication_data(connection)
+    // get the client
+    let client = Client::from_identifier(data.identifier.as_str(), connection)
         .await
         .unwrap()
-        .unwrap();
+        .map(|(client, _)| client);
 
-    authentication_data.get_qr_code(client.nickname().as_str())
+    // only verified emails can be used to login
+    if let Some(client) = client.filter(|client| *client.email_verified()) {
+        // the link can not replace a second factor
+        if has_second_factor(&client, connection).await {
+            return (
+                StatusCode::OK,
+                Json(json!({"message": "If the account exists, a mail has been sent"})),
+            );
+        }
+
+        // invalidate the previous links
+        for token in client
+            .verification_tokens(TokenPurpose::MagicLink, connection)
+            .await
+            .unwrap()
+        {
+            connection
+                .remove_by_column::<ClientVerificationToken, _>("uuid", token.uuid())
+                .await
+                .unwrap();
+        }
+
+        // setup the token
+        let token = ClientVerificationToken::new(client.sub().clone(), TokenPurpose::MagicLink);
+        connection.save(&token, &[]).await.unwrap();
+
+        // setup the mail
+        let link = format!("{}/magic_link?token={}", ROOT.as_str(), token.uuid());
+        let mail = locked.templates().render(
+            MailTemplate::MagicLink,
+            client.locale(),
+            &[
+                ("name", client.preferred_username().as_str()),
+                ("link", link.as_str()),
+            ],
+        );
+        let mail = MailOptions::from_template(client.email().clone(), mail);
+
+        // queue it, the delivery happens in the background
+        OutgoingMail::queue(mail, connection).await.unwrap();
+    }
+
+    // always answer the same way
+    (
+        StatusCode::OK,
+        Json(json!({"message": "If the account exists, a mail has been sent"})),
+    )
 }
 
-#[cfg(test)]
-impl Default for SignupRequest {
-    fn default() -> Self {
+/// Checks if the client has a totp or an authenticator registered
+async fn has_second_factor(client: &Client, connection: &Rbatis) -> bool {
+    let totp = client
+        .authentication_data(connection)
+        .await
+        .unwrap()
+        .map(|authentication_data| *authentication_data.totp())
+        .unwrap_or(false);
+
+    totp || !WebauthnCredential::of_client(client.sub(), connection)
+        .await
+        .unwrap()
+        .is_empty()
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct RedeemMagicLink {
+    /// the token from the mail
+    token: String,
+}
+
+pub async fn post_redeem_magic_link(
+    Extension(locator): Extension<LocatorPointer>,
+    address: Option<Extension<ClientAddress>>,
+    headers: HeaderMap,
+    Json(data): Json<RedeemMagicLink>,
+) -> impl IntoResponse {
+    // lock the locator
+    let mut locked = locator.lock().await;
+    let connection = locked.connection();
+
+    // get the token without consuming it yet
+    let uuid = Uuid::from_str(data.token.as_str()).map_err(|_| ResponseError::Unauthorized)?;
+    let token: Option<ClientVerificationToken> =
+        connection.fetch_by_column("uuid", &uuid).await.unwrap();
+    let token = token
+        .filter(|token| token.purpose() == &TokenPurpose::MagicLink)
+        .ok_or(ResponseError::Unauthorized)?;
+
+    // the link is subject to the same locks as the password login
+    let mut keys = vec![LoginAttempts::client_key(token.client())];
+    if let Some(Extension(address)) = &address {
+        keys.push(LoginAttempts::address_key(&address.0));
+    }
+    check_locks(keys.as_slice(), connection).await?;
+
+    // redeem the token
+    let token = ClientVerificationToken::redeem(&uuid, TokenPurpose::MagicLink, connection)
+        .await
+        .unwrap()
+        .ok_or(ResponseError::Unauthorized)?;
+    let client: Option<Client> = connection
+        .fetch_by_column("sub", token.client())
+        .await
+        .unwrap();
+    let client = client.ok_or(ResponseError::Unauthorized)?;
+
+    // the link can not replace a second factor
+    if has_second_factor(&client, connection).await {
+        return Err(ResponseError::BadRequest("Second factor required".into()));
+    }
+
+    // start the session
+    let session = locked
+        .auth_mut()
+        .start_session(client.sub().clone(), vec!["email".to_string()]);
+    notify_login(&client, address, &headers, &locked).await;
+
+    Ok((StatusCode::OK, Json(json!({ "session_id": session }))))
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct ActivateTOTP {
+    token: String,
+}
+
+pub async fn post_activate_totp(
+    Extension(locator): Extension<LocatorPointer>,
+    Extension(client): Extension<Client>,
+    Json(activate): Json<ActivateTOTP>,
+) -> impl IntoResponse {
+    // lock the locator
+    let locator = locator.lock().await;
+    let connection = locator.connection();
+
+    // get the auth data
+    let mut auth = client
+        .authentication_data(connection)
+        .await
+        .unwrap()
+        .unwrap();
+    // verify the token
+    if !auth.validate_totp(activate.token.as_str()) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(json!({"error": "Invalid token"})),
+        );
+    }
+
+    // update
+    auth.set_totp(true);
+    connection.update_by_column("uuid", &auth).await.unwrap();
+    // the codes are only shown once
+    let recovery_codes = RecoveryCode::regenerate(client.sub(), connection)
+        .await
+        .unwrap();
+    send_security_notice(&client, MailTemplate::TotpEnabled, &[], &locator).await;
+
+    (
+        StatusCode::OK,
+        Json(json!({"message": "Enabled", "recovery_codes": recovery_codes})),
+    )
+}
+
+pub async fn post_regenerate_recovery_codes(
+    Extension(locator): Extension<LocatorPointer>,
+    Extension(client): Extension<Client>,
+) -> Result<impl IntoResponse, ResponseError> {
+    // lock the locator
+    let locator = locator.lock().await;
+    let connection = locator.connection();
+
+    // get the auth data
+    let auth = client
+        .authentication_data(connection)
+        .await
+        .unwrap()
+        .unwrap();
+    if !auth.totp() {
+        return Err(ResponseError::BadRequest("TOTP is not enabled".to_string()));
+    }
+
+    // the old codes are invalidated
+    let recovery_codes = RecoveryCode::regenerate(client.sub(), connection)
+        .await
+        .unwrap();
+
+    Ok((
+        StatusCode::OK,
+        Json(json!({ "recovery_codes": recovery_codes })),
+    ))
+}
+
+pub async fn post_disable_totp(
+    Extension(locator): Extension<LocatorPointer>,
+    Extension(client): Extension<Client>,
+    Json(verification): Json<ActivateTOTP>,
+) -> impl IntoResponse {
+    // lock the locator
+    let locator = locator.lock().await;
+    let connection = locator.connection();
+    // get the auth data
+    let mut authentication_data = client
+        .authentication_data(&connection)
+        .await
+        .unwrap()
+        .unwrap();
+
+    // verify the token
+    if !authentication_data.validate_totp(verification.token.as_str()) {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(json!({"error": "Invalid token"})),
+        );
+    }
+
+    // regenerate the secret
+    authentication_data.set_secret(ClientAuthenticationData::gen_secret());
+    authentication_data.set_totp(false);
+    // update
+    connection
+        .update_by_column("uuid", &authentication_data)
+        .await
+        .unwrap();
+    RecoveryCode::invalidate(client.sub(), connection)
+        .await
+        .unwrap();
+    send_security_notice(&client, MailTemplate::TotpDisabled, &[], &locator).await;
+
+    (StatusCode::OK, Json(json!({"message": "Disabled"})))
+}
+
+pub async fn get_qr_code(
+    Extension(client): Extension<Client>,
+    Extension(locator): Extension<LocatorPointer>,
+) -> impl IntoResponse {
+    // lock
+    let locator = locator.lock().await;
+    let connection = locator.connection();
+
+    // get the auth data
+    let authentication_data = client
+        .authentication_data(connection)
+        .await
+        .unwrap()
+        .unwrap();
+
+    authentication_data.get_qr_code(client.nickname().as_str())
+}
+
+#[cfg(test)]
+impl Default for SignupRequest {
+    fn default() -> Self {
         Self {
             name: "Nick Name".to_string(),
             given_name: "Nick".to_string(),
@@ -353,7 +1009,7 @@ impl Default for SignupRequest {
             // TODO
             picture: "TODO".to_string(),
             website: None,
-            email: env!("TESTMAIL").to_string(),
+            email: "test@example.com".to_string(),
             gender: Gender::Other,
             birthdate: "".to_string(),
             zoneinfo: "Europe/Berlin".to_string(),
@@ -384,6 +1040,40 @@ mod tests {
         // parse the body
         let body = response.json::<Client>().await;
         assert_eq!(content.nickname, body.nickname().clone());
+
+        // verify the email with the link of the mail
+        let mails = TestSuite::deliver().await;
+        let mail = mails
+            .iter()
+            .find(|mail| mail.to() == &content.email)
+            .unwrap();
+        let response = connector
+            .post("/auth/verify_email")
+            .json(&VerifyEmail {
+                token: TestSuite::token(mail),
+            })
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_signup_weak_password() {
+        let (connector, _) = TestSuite::start().await;
+
+        // based on the nickname
+        let content = SignupRequest {
+            nickname: "Zumbrovicz".to_string(),
+            password: "zumbrovicz".to_string(),
+            ..SignupRequest::default()
+        };
+        let response = connector.post("/auth/signup").json(&content).send().await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        // the feedback of zxcvbn is returned
+        let body = response.json::<serde_json::Value>().await;
+        assert_eq!(body["error"], "Password not strong enough");
+        assert!(body["suggestions"].is_array());
     }
 
     #[tokio::test]
@@ -393,7 +1083,7 @@ mod tests {
 
         // build the body
         let body = AuthenticationRequest::builder()
-            .nickname("dfclient".into())
+            .identifier("dfclient".into())
             .password("password".into())
             .token(None)
             .build();
@@ -410,7 +1100,7 @@ mod tests {
 
         // build the body
         let body = AuthenticationRequest::builder()
-            .nickname("client".into())
+            .identifier("client".into())
             .password("password".into())
             .token(None)
             .build();
@@ -427,7 +1117,7 @@ mod tests {
 
         // build the body
         let body = AuthenticationRequest::builder()
-            .nickname("dfclient".into())
+            .identifier("dfclient".into())
             .password("wdwad".into())
             .token(None)
             .build();
@@ -462,6 +1152,32 @@ mod tests {
         suite.authenticate("dfclient", password).await;
     }
 
+    #[tokio::test]
+    async fn test_password_change_weak_password() {
+        let suite = TestSuite::new().await;
+        let authorization = suite.authenticate("dfclient", "password").await;
+
+        let response = suite
+            .connector
+            .put("/auth/password")
+            .json(&UpdatePassword {
+                password: "password".to_string(),
+            })
+            .header(AUTHORIZATION, authorization)
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        // the feedback of zxcvbn is returned
+        let body = response.json::<serde_json::Value>().await;
+        assert_eq!(body["error"], "Password not strong enough");
+        assert!(body["warning"].is_string());
+        assert!(!body["suggestions"].as_array().unwrap().is_empty());
+
+        // the password is unchanged
+        suite.authenticate("dfclient", "password").await;
+    }
+
     #[tokio::test]
     #[should_panic]
     async fn test_activate_totp() {
@@ -486,4 +1202,531 @@ mod tests {
         // should panic here
         suite.authenticate("dfclient", "password").await;
     }
+
+    #[tokio::test]
+    async fn test_rehash() {
+        let suite = TestSuite::new().await;
+
+        // store a hash with the previous argon2d defaults
+        let config = argon2::Config {
+            variant: argon2::Variant::Argon2d,
+            ..Default::default()
+        };
+        let hash = argon2::hash_encoded(b"password", b"somesaltsomesalt", &config).unwrap();
+        let mut authentication_data = suite.authentication_data.clone();
+        authentication_data.set_password(hash);
+        suite
+            .connection
+            .update_by_column("uuid", &authentication_data)
+            .await
+            .unwrap();
+
+        // upgraded on login
+        suite.authenticate("dfclient", "password").await;
+        let authentication_data = suite
+            .client
+            .authentication_data(&suite.connection)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(authentication_data.password().starts_with("$argon2id$"));
+
+        // still valid
+        suite.authenticate("dfclient", "password").await;
+    }
+
+    #[tokio::test]
+    async fn test_recovery_codes() {
+        let suite = TestSuite::new().await;
+        let authorization = suite.authenticate("dfclient", "password").await;
+
+        // activate the totp
+        let body = ActivateTOTP {
+            token: GoogleAuthenticator::new()
+                .get_code(suite.authentication_data.secret().as_str(), 0)
+                .unwrap(),
+        };
+        let response = suite
+            .connector
+            .post("/auth/totp")
+            .json(&body)
+            .header(AUTHORIZATION, &authorization)
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.json::<serde_json::Value>().await;
+        let codes = body.get("recovery_codes").unwrap().as_array().unwrap();
+        assert_eq!(codes.len(), crate::database::recovery::CODE_COUNT);
+
+        // login with a recovery code in place of the token
+        let login = AuthenticationRequest::builder()
+            .identifier("dfclient".into())
+            .password("password".into())
+            .token(Some(codes[0].as_str().unwrap().to_string()))
+            .build();
+        let response = suite
+            .connector
+            .post("/auth/login")
+            .json(&login)
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        // single use
+        let response = suite
+            .connector
+            .post("/auth/login")
+            .json(&login)
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        // regenerate
+        let response = suite
+            .connector
+            .post("/auth/totp/recovery_codes")
+            .header(AUTHORIZATION, &authorization)
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // the old codes are invalid now
+        let login = AuthenticationRequest::builder()
+            .identifier("dfclient".into())
+            .password("password".into())
+            .token(Some(codes[1].as_str().unwrap().to_string()))
+            .build();
+        let response = suite
+            .connector
+            .post("/auth/login")
+            .json(&login)
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_verify_email() {
+        let suite = TestSuite::new().await;
+
+        // create the token
+        let token = ClientVerificationToken::new(
+            suite.client.sub().clone(),
+            TokenPurpose::EmailVerification,
+        );
+        suite.connection.save(&token, &[]).await.unwrap();
+
+        // redeem it
+        let body = VerifyEmail {
+            token: token.uuid().to_string(),
+        };
+        let response = suite
+            .connector
+            .post("/auth/verify_email")
+            .json(&body)
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // the email is verified now
+        let client: Client = suite
+            .connection
+            .fetch_by_column("sub", suite.client.sub())
+            .await
+            .unwrap();
+        assert!(client.email_verified());
+
+        // the token can only be used once
+        let response = suite
+            .connector
+            .post("/auth/verify_email")
+            .json(&body)
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_resend_verification() {
+        let suite = TestSuite::new().await;
+        let authorization = suite.authenticate("dfclient", "password").await;
+
+        let response = suite
+            .connector
+            .post("/auth/verify_email/resend")
+            .header(AUTHORIZATION, &authorization)
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // limited
+        let response = suite
+            .connector
+            .post("/auth/verify_email/resend")
+            .header(AUTHORIZATION, authorization)
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[tokio::test]
+    async fn test_request_password_reset() {
+        let suite = TestSuite::new().await;
+
+        // the response does not depend on the existence of the account
+        let known = suite
+            .connector
+            .post("/auth/password/reset/request")
+            .json(&RequestPasswordReset {
+                identifier: "dfclient".into(),
+            })
+            .send()
+            .await;
+        let unknown = suite
+            .connector
+            .post("/auth/password/reset/request")
+            .json(&RequestPasswordReset {
+                identifier: "unknown".into(),
+            })
+            .send()
+            .await;
+        assert_eq!(known.status(), StatusCode::OK);
+        assert_eq!(unknown.status(), StatusCode::OK);
+        assert_eq!(known.text().await, unknown.text().await);
+
+        // only the known account got a token
+        let tokens = suite
+            .client
+            .verification_tokens(TokenPurpose::PasswordReset, &suite.connection)
+            .await
+            .unwrap();
+        assert_eq!(tokens.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_reset_password() {
+        let suite = TestSuite::new().await;
+        let authorization = suite.authenticate("dfclient", "password").await;
+
+        // create the token
+        let token =
+            ClientVerificationToken::new(suite.client.sub().clone(), TokenPurpose::PasswordReset);
+        suite.connection.save(&token, &[]).await.unwrap();
+
+        // reset
+        let password = "658t7igGyuAhi@ljoeWADrfp%";
+        let body = ResetPassword {
+            token: token.uuid().to_string(),
+            password: password.to_string(),
+        };
+        let response = suite
+            .connector
+            .post("/auth/password/reset")
+            .json(&body)
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // the old session is canceled
+        let response = suite
+            .connector
+            .get("/client/me")
+            .header(AUTHORIZATION, authorization)
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        // login with the new password (would panic on failure)
+        suite.authenticate("dfclient", password).await;
+
+        // the token can only be used once
+        let response = suite
+            .connector
+            .post("/auth/password/reset")
+            .json(&body)
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_request_magic_link() {
+        let suite = TestSuite::new().await;
+
+        // unverified emails can not be used
+        let body = RequestMagicLink {
+            identifier: "dfclient".into(),
+        };
+        let response = suite
+            .connector
+            .post("/auth/magic_link")
+            .json(&body)
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let tokens = suite
+            .client
+            .verification_tokens(TokenPurpose::MagicLink, &suite.connection)
+            .await
+            .unwrap();
+        assert!(tokens.is_empty());
+
+        // verify the email
+        let mut client = suite.client.clone();
+        client.set_email_verified(true);
+        suite
+            .connection
+            .update_by_column("sub", &client)
+            .await
+            .unwrap();
+
+        let response = suite
+            .connector
+            .post("/auth/magic_link")
+            .json(&body)
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let tokens = suite
+            .client
+            .verification_tokens(TokenPurpose::MagicLink, &suite.connection)
+            .await
+            .unwrap();
+        assert_eq!(tokens.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_redeem_magic_link() {
+        let suite = TestSuite::new().await;
+
+        // create the token
+        let token =
+            ClientVerificationToken::new(suite.client.sub().clone(), TokenPurpose::MagicLink);
+        suite.connection.save(&token, &[]).await.unwrap();
+
+        // redeem it
+        let body = RedeemMagicLink {
+            token: token.uuid().to_string(),
+        };
+        let response = suite
+            .connector
+            .post("/auth/magic_link/redeem")
+            .json(&body)
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // the session is usable
+        let session_id = response.json::<serde_json::Value>().await["session_id"]
+            .as_str()
+            .unwrap()
+            .to_string();
+        let response = suite
+            .connector
+            .get("/client/me")
+            .header(AUTHORIZATION, session_id)
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // the link can only be used once
+        let response = suite
+            .connector
+            .post("/auth/magic_link/redeem")
+            .json(&body)
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_redeem_magic_link_locked() {
+        let suite = TestSuite::new().await;
+
+        // lock the account
+        let key = LoginAttempts::client_key(suite.client.sub());
+        for _ in 0..*crate::LOGIN_LOCK_AFTER {
+            LoginAttempts::fail(key.clone(), &suite.connection)
+                .await
+                .unwrap();
+        }
+
+        let token =
+            ClientVerificationToken::new(suite.client.sub().clone(), TokenPurpose::MagicLink);
+        suite.connection.save(&token, &[]).await.unwrap();
+        let response = suite
+            .connector
+            .post("/auth/magic_link/redeem")
+            .json(&RedeemMagicLink {
+                token: token.uuid().to_string(),
+            })
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        // the link is not consumed
+        let token: Option<ClientVerificationToken> = suite
+            .connection
+            .fetch_by_column("uuid", token.uuid())
+            .await
+            .unwrap();
+        assert!(token.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_redeem_magic_link_second_factor() {
+        let suite = TestSuite::new().await;
+
+        // verify the email and enable the totp
+        let mut client = suite.client.clone();
+        client.set_email_verified(true);
+        suite
+            .connection
+            .update_by_column("sub", &client)
+            .await
+            .unwrap();
+        let mut authentication_data = suite.authentication_data.clone();
+        authentication_data.set_totp(true);
+        suite
+            .connection
+            .update_by_column("uuid", &authentication_data)
+            .await
+            .unwrap();
+
+        // no link is sent
+        let response = suite
+            .connector
+            .post("/auth/magic_link")
+            .json(&RequestMagicLink {
+                identifier: "dfclient".into(),
+            })
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(suite
+            .client
+            .verification_tokens(TokenPurpose::MagicLink, &suite.connection)
+            .await
+            .unwrap()
+            .is_empty());
+
+        // an existing link can not be used to skip the totp
+        let token =
+            ClientVerificationToken::new(suite.client.sub().clone(), TokenPurpose::MagicLink);
+        suite.connection.save(&token, &[]).await.unwrap();
+        let response = suite
+            .connector
+            .post("/auth/magic_link/redeem")
+            .json(&RedeemMagicLink {
+                token: token.uuid().to_string(),
+            })
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_security_notices() {
+        let suite = TestSuite::new().await;
+        let authorization = suite.authenticate("dfclient", "password").await;
+
+        // change the password
+        let response = suite
+            .connector
+            .put("/auth/password")
+            .json(&UpdatePassword {
+                password: "658t7igGyuAhi@ljoeWADrfp%".to_string(),
+            })
+            .header(AUTHORIZATION, authorization)
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // one notice for the login and one for the change
+        let notices = TestSuite::deliver()
+            .await
+            .into_iter()
+            .filter(|mail| mail.to() == suite.client.email())
+            .collect::<Vec<_>>();
+        assert_eq!(notices.len(), 2);
+        assert!(notices.iter().all(|mail| mail.text().contains("/sessions")));
+    }
+
+    #[tokio::test]
+    async fn test_login_email() {
+        let suite = TestSuite::new().await;
+
+        // the email ignores the case
+        let body = AuthenticationRequest::builder()
+            .identifier(suite.client.email().to_uppercase())
+            .password("password".into())
+            .token(None)
+            .build();
+        let response = suite.connector.post("/auth/login").json(&body).send().await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_lockout() {
+        let suite = TestSuite::new().await;
+        let body = AuthenticationRequest::builder()
+            .identifier("dfclient".into())
+            .password("wrong".into())
+            .token(None)
+            .build();
+
+        // fail until the attempts are delayed
+        for _ in 0..*crate::LOGIN_DELAY_AFTER {
+            let response = suite.connector.post("/auth/login").json(&body).send().await;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        }
+        let response = suite.connector.post("/auth/login").json(&body).send().await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(response.headers().get("retry-after").is_some());
+
+        // unlocked by the admin
+        let response = suite
+            .connector
+            .post("/admin/unlock")
+            .json(&AdminUnlock {
+                identifier: "dfclient".into(),
+            })
+            .header(AUTHORIZATION, crate::ADMIN_KEY.as_str())
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        suite.authenticate("dfclient", "password").await;
+    }
+
+    #[tokio::test]
+    async fn test_unlock() {
+        let suite = TestSuite::new().await;
+
+        // lock the account
+        let key = LoginAttempts::client_key(suite.client.sub());
+        for _ in 0..*crate::LOGIN_LOCK_AFTER {
+            LoginAttempts::fail(key.clone(), &suite.connection)
+                .await
+                .unwrap();
+        }
+        assert!(LoginAttempts::get(key.as_str(), &suite.connection)
+            .await
+            .unwrap()
+            .unwrap()
+            .is_locked());
+
+        // unlock it with the token
+        let token =
+            ClientVerificationToken::new(suite.client.sub().clone(), TokenPurpose::AccountUnlock);
+        suite.connection.save(&token, &[]).await.unwrap();
+        let response = suite
+            .connector
+            .post("/auth/unlock")
+            .json(&UnlockAccount {
+                token: token.uuid().to_string(),
+            })
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        suite.authenticate("dfclient", "password").await;
+    }
 }
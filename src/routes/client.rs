@@ -23,12 +23,25 @@
  *  SOFTWARE.
  */
 
-use crate::database::client::{Client, Gender};
+use crate::database::client::{Client, ClientVerificationToken, Gender, TokenPurpose};
+use crate::database::outbox::OutgoingMail;
+use crate::database::phone::PhoneVerificationCode;
+use crate::database::unique_violation;
+use crate::error::ResponseError;
+use crate::locator::mail::MailOptions;
+use crate::locator::template::MailTemplate;
 use crate::locator::LocatorPointer;
+use crate::openid::verification::Verification;
+use crate::routes::send_security_notice;
+use crate::{ROOT, VERIFICATION_RESEND_INTERVAL};
 use axum::http::StatusCode;
 use axum::response::IntoResponse;
 use axum::{Extension, Json};
+use chrono::{Duration, Utc};
 use rbatis::crud::CRUD;
+use rbatis::rbatis::Rbatis;
+use rbatis::{TimestampZ, Uuid};
+use std::str::FromStr;
 
 pub async fn post_delete(
     Extension(locator): Extension<LocatorPointer>,
@@ -37,6 +50,8 @@ pub async fn post_delete(
     // lock the locator
     let locked = locator.lock().await;
 
+    // notify before the data is gone
+    send_security_notice(&client, MailTemplate::AccountDeleted, &[], &locked).await;
     // delete the client
     client.delete(locked.connection()).await;
     (StatusCode::OK, Json(json!({"message": "Deleted"})))
@@ -124,13 +139,314 @@ pub async fn put_address(
     (StatusCode::OK, Json(address.clone()))
 }
 
+/// Remove all tokens of the client with the given purpose
+async fn remove_tokens(client: &Client, purpose: TokenPurpose, connection: &Rbatis) {
+    for token in client
+        .verification_tokens(purpose, connection)
+        .await
+        .unwrap()
+    {
+        connection
+            .remove_by_column::<ClientVerificationToken, _>("uuid", token.uuid())
+            .await
+            .unwrap();
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct UpdateEmail {
+    email: String,
+}
+
+pub async fn put_email(
+    Extension(locator): Extension<LocatorPointer>,
+    Json(update): Json<UpdateEmail>,
+    Extension(client): Extension<Client>,
+) -> impl IntoResponse {
+    // validate the email (check via regex)
+    if !Verification::email_valid(update.email.as_str()) {
+        return Err(ResponseError::BadRequest("Email not valid".into()));
+    }
+
+    // lock the locator
+    let locked = locator.lock().await;
+    let connection = locked.connection();
+
+    // the email has to be unused
+    if Client::from_email(update.email.as_str(), connection)
+        .await
+        .unwrap()
+        .is_some()
+    {
+        return Err(ResponseError::Conflict("Email already in use".into()));
+    }
+
+    // only the latest change is pending
+    remove_tokens(&client, TokenPurpose::EmailChange, connection).await;
+    remove_tokens(&client, TokenPurpose::EmailChangeCancel, connection).await;
+
+    // setup the tokens
+    let confirm = ClientVerificationToken::with_payload(
+        client.sub().clone(),
+        TokenPurpose::EmailChange,
+        update.email.clone(),
+    );
+    let cancel =
+        ClientVerificationToken::new(client.sub().clone(), TokenPurpose::EmailChangeCancel);
+    connection.save(&confirm, &[]).await.unwrap();
+    connection.save(&cancel, &[]).await.unwrap();
+
+    // the confirmation goes to the new address
+    let link = format!("{}/confirm_email?token={}", ROOT.as_str(), confirm.uuid());
+    let mail = locked.templates().render(
+        MailTemplate::EmailChange,
+        client.locale(),
+        &[
+            ("name", client.preferred_username().as_str()),
+            ("link", link.as_str()),
+        ],
+    );
+    let mail = MailOptions::from_template(update.email.clone(), mail);
+    OutgoingMail::queue(mail, connection).await.unwrap();
+
+    // notify the old address
+    let link = format!(
+        "{}/cancel_email_change?token={}",
+        ROOT.as_str(),
+        cancel.uuid()
+    );
+    let mail = locked.templates().render(
+        MailTemplate::EmailChangeNotice,
+        client.locale(),
+        &[
+            ("name", client.preferred_username().as_str()),
+            ("email", update.email.as_str()),
+            ("link", link.as_str()),
+        ],
+    );
+    let mail = MailOptions::from_template(client.email().clone(), mail);
+    OutgoingMail::queue(mail, connection).await.unwrap();
+
+    Ok((
+        StatusCode::OK,
+        Json(json!({"message": "Confirmation sent"})),
+    ))
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct EmailToken {
+    /// the token from the mail
+    token: String,
+}
+
+pub async fn post_confirm_email(
+    Extension(locator): Extension<LocatorPointer>,
+    Json(data): Json<EmailToken>,
+) -> impl IntoResponse {
+    // lock the locator
+    let locked = locator.lock().await;
+    let connection = locked.connection();
+
+    // redeem the token
+    let token = match Uuid::from_str(data.token.as_str()) {
+        Ok(uuid) => ClientVerificationToken::redeem(&uuid, TokenPurpose::EmailChange, connection)
+            .await
+            .unwrap(),
+        Err(_) => None,
+    }
+    .ok_or_else(|| ResponseError::BadRequest("Invalid token".into()))?;
+
+    // get the client
+    let client: Option<Client> = connection
+        .fetch_by_column("sub", token.client())
+        .await
+        .unwrap();
+    let mut client = client.ok_or_else(|| ResponseError::BadRequest("Invalid token".into()))?;
+
+    // the notice goes to the old address
+    let previous = client.clone();
+
+    // update the email, it is verified by the token
+    client
+        .set_email(token.payload().clone().unwrap())
+        .set_email_verified(true)
+        .set_updated_at(TimestampZ::now());
+    if let Err(error) = connection.update_by_column("sub", &client).await {
+        // the email could have been taken in the meantime
+        if unique_violation(&error, "clients_email_key") {
+            return Err(ResponseError::Conflict("Email already in use".into()));
+        }
+
+        // restore the token, so the change can be confirmed again
+        connection.save(&token, &[]).await.ok();
+        return Err(ResponseError::Internal(error.to_string()));
+    }
+
+    // the change can not be canceled anymore
+    remove_tokens(&client, TokenPurpose::EmailChangeCancel, connection).await;
+    send_security_notice(
+        &previous,
+        MailTemplate::EmailChanged,
+        &[("email", client.email().as_str())],
+        &locked,
+    )
+    .await;
+
+    Ok((StatusCode::OK, Json(client)))
+}
+
+pub async fn post_cancel_email_change(
+    Extension(locator): Extension<LocatorPointer>,
+    Json(data): Json<EmailToken>,
+) -> impl IntoResponse {
+    // lock the locator
+    let locked = locator.lock().await;
+    let connection = locked.connection();
+
+    // redeem the token
+    let token = match Uuid::from_str(data.token.as_str()) {
+        Ok(uuid) => {
+            ClientVerificationToken::redeem(&uuid, TokenPurpose::EmailChangeCancel, connection)
+                .await
+                .unwrap()
+        }
+        Err(_) => None,
+    }
+    .ok_or_else(|| ResponseError::BadRequest("Invalid token".into()))?;
+
+    // remove the pending change, the tokens of other flows stay valid
+    let client: Option<Client> = connection
+        .fetch_by_column("sub", token.client())
+        .await
+        .unwrap();
+    if let Some(client) = client {
+        remove_tokens(&client, TokenPurpose::EmailChange, connection).await;
+        remove_tokens(&client, TokenPurpose::EmailChangeCancel, connection).await;
+    }
+
+    Ok((StatusCode::OK, Json(json!({"message": "Canceled"}))))
+}
+
+pub async fn post_request_phone_verification(
+    Extension(locator): Extension<LocatorPointer>,
+    Extension(client): Extension<Client>,
+) -> impl IntoResponse {
+    let phone_number = client
+        .phone_number()
+        .clone()
+        .ok_or_else(|| ResponseError::BadRequest("No phone number set".into()))?;
+    if *client.phone_number_verified() {
+        return Err(ResponseError::BadRequest(
+            "Phone number already verified".into(),
+        ));
+    }
+
+    // lock the locator
+    let locked = locator.lock().await;
+    let connection = locked.connection();
+
+    // limit how often the code can be sent
+    let previous: Option<PhoneVerificationCode> = connection
+        .fetch_by_column("client", client.sub())
+        .await
+        .unwrap();
+    if let Some(previous) = previous {
+        if previous.created_at().inner + Duration::minutes(*VERIFICATION_RESEND_INTERVAL)
+            > Utc::now()
+        {
+            return Err(ResponseError::TooManyRequests);
+        }
+        connection
+            .remove_by_column::<PhoneVerificationCode, _>("client", client.sub())
+            .await
+            .unwrap();
+    }
+
+    // setup the code
+    let (stored, code) = PhoneVerificationCode::generate(client.sub().clone(), phone_number);
+    connection.save(&stored, &[]).await.unwrap();
+
+    // send it without holding the locator
+    let sms = locked.sms().clone();
+    drop(locked);
+    let message = format!("Your verification code is {}", code);
+    if let Err(error) = sms.send(stored.phone_number(), message.as_str()).await {
+        // the code never arrived, so it must not block a retry
+        locator
+            .lock()
+            .await
+            .connection()
+            .remove_by_column::<PhoneVerificationCode, _>("client", client.sub())
+            .await
+            .unwrap();
+
+        return Err(ResponseError::Internal(format!(
+            "Failed to send the phone verification code: {}",
+            error
+        )));
+    }
+
+    Ok((StatusCode::OK, Json(json!({"message": "Sent"}))))
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct VerifyPhone {
+    /// the code from the sms
+    code: String,
+}
+
+pub async fn post_verify_phone(
+    Extension(locator): Extension<LocatorPointer>,
+    Extension(mut client): Extension<Client>,
+    Json(data): Json<VerifyPhone>,
+) -> impl IntoResponse {
+    // lock the locator
+    let locked = locator.lock().await;
+    let connection = locked.connection();
+
+    let stored: Option<PhoneVerificationCode> = connection
+        .fetch_by_column("client", client.sub())
+        .await
+        .unwrap();
+    let mut stored = stored.ok_or_else(|| ResponseError::BadRequest("Invalid code".into()))?;
+
+    // the code is only valid for the number it was sent to
+    if client.phone_number().as_deref() != Some(stored.phone_number().as_str())
+        || !stored.verify(data.code.as_str())
+    {
+        // count the attempt
+        connection
+            .update_by_column("client", &stored)
+            .await
+            .unwrap();
+        return Err(ResponseError::BadRequest("Invalid code".into()));
+    }
+
+    // update
+    client
+        .set_phone_number_verified(true)
+        .set_updated_at(TimestampZ::now());
+    connection.update_by_column("sub", &client).await.unwrap();
+    connection
+        .remove_by_column::<PhoneVerificationCode, _>("client", client.sub())
+        .await
+        .unwrap();
+
+    Ok((StatusCode::OK, Json(json!({"message": "Verified"}))))
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::database::client::{Address, Client};
-    use crate::routes::client::{UpdateAddress, UpdateClient};
+    use crate::database::client::{Address, Client, TokenPurpose};
+    use crate::locator::sms::{MemorySms, SmsError, SmsSender};
+    use crate::routes::client::{
+        EmailToken, UpdateAddress, UpdateClient, UpdateEmail, VerifyPhone,
+    };
     use crate::tests::TestSuite;
     use axum::http::header::AUTHORIZATION;
     use axum::http::StatusCode;
+    use rbatis::crud::CRUD;
+    use std::sync::Arc;
 
     #[tokio::test]
     async fn test_delete() {
@@ -243,4 +559,306 @@ mod tests {
             "Hell Yea"
         );
     }
+
+    #[tokio::test]
+    async fn test_change_email() {
+        let suite = TestSuite::new().await;
+        let authorization = suite.authenticate("dfclient", "password").await;
+
+        // request the change
+        let response = suite
+            .connector
+            .put("/client/me/email")
+            .json(&UpdateEmail {
+                email: "changed@example.com".to_string(),
+            })
+            .header(AUTHORIZATION, authorization)
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // still pending
+        let client: Client = suite
+            .connection
+            .fetch_by_column("sub", suite.client.sub())
+            .await
+            .unwrap();
+        assert_eq!(client.email(), suite.client.email());
+
+        // confirm
+        let token = client
+            .verification_tokens(TokenPurpose::EmailChange, &suite.connection)
+            .await
+            .unwrap()
+            .remove(0);
+        let response = suite
+            .connector
+            .post("/client/me/email/confirm")
+            .json(&EmailToken {
+                token: token.uuid().to_string(),
+            })
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let client = response.json::<Client>().await;
+        assert_eq!(client.email().as_str(), "changed@example.com");
+        assert!(client.email_verified());
+    }
+
+    #[tokio::test]
+    async fn test_change_email_conflict() {
+        let suite = TestSuite::new().await;
+        let authorization = suite.authenticate("dfclient", "password").await;
+
+        // create another client with the email
+        let mut other = Client::default();
+        other
+            .set_nickname("other".to_string())
+            .set_email("other@example.com".to_string());
+        suite.connection.save(&other, &[]).await.unwrap();
+
+        let response = suite
+            .connector
+            .put("/client/me/email")
+            .json(&UpdateEmail {
+                email: "other@example.com".to_string(),
+            })
+            .header(AUTHORIZATION, authorization)
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+    }
+
+    #[tokio::test]
+    async fn test_cancel_email_change() {
+        let suite = TestSuite::new().await;
+        let authorization = suite.authenticate("dfclient", "password").await;
+
+        // request the change
+        suite
+            .connector
+            .put("/client/me/email")
+            .json(&UpdateEmail {
+                email: "changed@example.com".to_string(),
+            })
+            .header(AUTHORIZATION, authorization)
+            .send()
+            .await;
+
+        // cancel it
+        let token = suite
+            .client
+            .verification_tokens(TokenPurpose::EmailChangeCancel, &suite.connection)
+            .await
+            .unwrap()
+            .remove(0);
+        let response = suite
+            .connector
+            .post("/client/me/email/cancel")
+            .json(&EmailToken {
+                token: token.uuid().to_string(),
+            })
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // nothing left to confirm
+        assert!(suite
+            .client
+            .verification_tokens(TokenPurpose::EmailChange, &suite.connection)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_cancel_email_change_keeps_other_tokens() {
+        let suite = TestSuite::new().await;
+        let authorization = suite.authenticate("dfclient", "password").await;
+
+        // a pending password reset
+        let reset =
+            ClientVerificationToken::new(suite.client.sub().clone(), TokenPurpose::PasswordReset);
+        suite.connection.save(&reset, &[]).await.unwrap();
+
+        // request and cancel the change
+        suite
+            .connector
+            .put("/client/me/email")
+            .json(&UpdateEmail {
+                email: "changed@example.com".to_string(),
+            })
+            .header(AUTHORIZATION, authorization)
+            .send()
+            .await;
+        let token = suite
+            .client
+            .verification_tokens(TokenPurpose::EmailChangeCancel, &suite.connection)
+            .await
+            .unwrap()
+            .remove(0);
+        let response = suite
+            .connector
+            .post("/client/me/email/cancel")
+            .json(&EmailToken {
+                token: token.uuid().to_string(),
+            })
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // the reset token survives
+        let tokens = suite
+            .client
+            .verification_tokens(TokenPurpose::PasswordReset, &suite.connection)
+            .await
+            .unwrap();
+        assert_eq!(tokens.len(), 1);
+        assert_eq!(tokens[0].uuid(), reset.uuid());
+    }
+
+    #[tokio::test]
+    async fn test_confirm_email_taken() {
+        let suite = TestSuite::new().await;
+        let authorization = suite.authenticate("dfclient", "password").await;
+
+        // request the change
+        suite
+            .connector
+            .put("/client/me/email")
+            .json(&UpdateEmail {
+                email: "changed@example.com".to_string(),
+            })
+            .header(AUTHORIZATION, authorization)
+            .send()
+            .await;
+
+        // the email is taken before the confirmation
+        let mut other = Client::default();
+        other
+            .set_nickname("other".to_string())
+            .set_email("changed@example.com".to_string());
+        suite.connection.save(&other, &[]).await.unwrap();
+
+        let token = suite
+            .client
+            .verification_tokens(TokenPurpose::EmailChange, &suite.connection)
+            .await
+            .unwrap()
+            .remove(0);
+        let response = suite
+            .connector
+            .post("/client/me/email/confirm")
+            .json(&EmailToken {
+                token: token.uuid().to_string(),
+            })
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+    }
+
+    /// A provider which is always unavailable
+    struct FailingSms;
+
+    #[async_trait]
+    impl SmsSender for FailingSms {
+        async fn send(&self, _: &str, _: &str) -> Result<(), SmsError> {
+            Err(SmsError::Status(503))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_request_phone_verification_failed() {
+        let suite = TestSuite::new().await;
+        let authorization = suite.authenticate("dfclient", "password").await;
+
+        // set a phone number
+        let mut client = suite.client.clone();
+        client.set_phone_number(Some("+49123456789".to_string()));
+        suite
+            .connection
+            .update_by_column("sub", &client)
+            .await
+            .unwrap();
+
+        *suite.locator.lock().await.sms_mut() = Arc::new(FailingSms);
+        let response = suite
+            .connector
+            .post("/client/me/phone/verify/request")
+            .header(AUTHORIZATION, &authorization)
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+        // the retry is not blocked by the resend interval
+        let sms = MemorySms::default();
+        *suite.locator.lock().await.sms_mut() = Arc::new(sms.clone());
+        let response = suite
+            .connector
+            .post("/client/me/phone/verify/request")
+            .header(AUTHORIZATION, &authorization)
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(sms.messages().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_verify_phone() {
+        let suite = TestSuite::new().await;
+        let authorization = suite.authenticate("dfclient", "password").await;
+
+        // set a phone number
+        let mut client = suite.client.clone();
+        client.set_phone_number(Some("+49123456789".to_string()));
+        suite
+            .connection
+            .update_by_column("sub", &client)
+            .await
+            .unwrap();
+
+        // capture the sms
+        let sms = MemorySms::default();
+        *suite.locator.lock().await.sms_mut() = Arc::new(sms.clone());
+
+        let response = suite
+            .connector
+            .post("/client/me/phone/verify/request")
+            .header(AUTHORIZATION, &authorization)
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let (to, message) = sms.messages().remove(0);
+        assert_eq!(to, "+49123456789");
+        let code = message.split_whitespace().last().unwrap().to_string();
+
+        // wrong codes are rejected
+        let response = suite
+            .connector
+            .post("/client/me/phone/verify")
+            .json(&VerifyPhone {
+                code: "wrong".to_string(),
+            })
+            .header(AUTHORIZATION, &authorization)
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response = suite
+            .connector
+            .post("/client/me/phone/verify")
+            .json(&VerifyPhone { code })
+            .header(AUTHORIZATION, &authorization)
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let client: Client = suite
+            .connection
+            .fetch_by_column("sub", suite.client.sub())
+            .await
+            .unwrap();
+        assert!(client.phone_number_verified());
+    }
 }
@@ -0,0 +1,185 @@
+/*
+ * The MIT License (MIT)
+ *
+ * Copyright (c) 2022 Randoooom
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in the
+ * Software without restriction, including without limitation the rights to use, copy,
+ * modify, merge, publish, distribute, sublicense, and/or sell copies of the Software,
+ * and to permit persons to whom the Software is furnished to do so, subject to the
+ * following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+ * EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+ * MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+ * NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT
+ * HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR
+ * IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ *  SOFTWARE.
+ */
+
+use crate::error::ResponseError;
+use crate::locator::LocatorPointer;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::{Extension, Json};
+
+pub async fn get_keys(Extension(locator): Extension<LocatorPointer>) -> impl IntoResponse {
+    // lock the locator
+    let locked = locator.lock().await;
+
+    // collect the public keys
+    let keys = locked
+        .paseto()
+        .keys()
+        .iter()
+        .map(|key| {
+            json!({
+                "kid": key.pid(),
+                "paserk": key.paserk(),
+                "state": key.state(),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    (StatusCode::OK, Json(json!({ "keys": keys })))
+}
+
+pub async fn post_generate_key(
+    Extension(locator): Extension<LocatorPointer>,
+) -> Result<impl IntoResponse, ResponseError> {
+    // lock the locator
+    let mut locked = locator.lock().await;
+
+    // a rotation which is lost on restart would invalidate the issued tokens
+    if !locked.paseto().persistent() {
+        return Err(ResponseError::Conflict(
+            "Key rotation requires the database key source".into(),
+        ));
+    }
+
+    // generate the new pending key
+    let pid = locked.paseto_mut().generate();
+    // persist it
+    locked
+        .paseto()
+        .save(locked.connection())
+        .await
+        .map_err(|error| ResponseError::Internal(error.to_string()))?;
+
+    Ok((StatusCode::CREATED, Json(json!({ "kid": pid }))))
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct PromoteKey {
+    /// the pid of the pending key
+    kid: String,
+}
+
+pub async fn post_promote_key(
+    Extension(locator): Extension<LocatorPointer>,
+    Json(data): Json<PromoteKey>,
+) -> Result<impl IntoResponse, ResponseError> {
+    // lock the locator
+    let mut locked = locator.lock().await;
+
+    if !locked.paseto().persistent() {
+        return Err(ResponseError::Conflict(
+            "Key rotation requires the database key source".into(),
+        ));
+    }
+
+    // promote the key
+    if !locked.paseto_mut().promote(data.kid.as_str()) {
+        return Err(ResponseError::NotFound("No pending key found".into()));
+    }
+    // persist the rotation
+    locked
+        .paseto()
+        .save(locked.connection())
+        .await
+        .map_err(|error| ResponseError::Internal(error.to_string()))?;
+
+    Ok((StatusCode::OK, Json(json!({"message": "Promoted"}))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::locator::paseto::TokenSigner;
+    use crate::tests::TestSuite;
+    use crate::ADMIN_KEY;
+    use axum::http::header::AUTHORIZATION;
+
+    #[tokio::test]
+    async fn test_rotation() {
+        let (connector, _, locator) = TestSuite::start_with_locator().await;
+        *locator.lock().await.paseto_mut() = TokenSigner::in_database([7u8; 32]);
+
+        // unauthorized without the admin key
+        let response = connector.post("/admin/keys").send().await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        // generate a new key
+        let response = connector
+            .post("/admin/keys")
+            .header(AUTHORIZATION, ADMIN_KEY.as_str())
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let kid = response.json::<serde_json::Value>().await["kid"]
+            .as_str()
+            .unwrap()
+            .to_string();
+
+        // promote it
+        let response = connector
+            .post("/admin/keys/promote")
+            .json(&PromoteKey { kid: kid.clone() })
+            .header(AUTHORIZATION, ADMIN_KEY.as_str())
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // only pending keys can be promoted
+        let response = connector
+            .post("/admin/keys/promote")
+            .json(&PromoteKey { kid: kid.clone() })
+            .header(AUTHORIZATION, ADMIN_KEY.as_str())
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        // both keys are published
+        let response = connector.get("/keys").send().await;
+        let body = response.json::<serde_json::Value>().await;
+        let keys = body["keys"].as_array().unwrap();
+        assert_eq!(keys.len(), 2);
+        assert!(keys
+            .iter()
+            .any(|key| key["kid"] == kid.as_str() && key["state"] == "Active"));
+    }
+
+    #[tokio::test]
+    async fn test_rotation_file_source() {
+        let (connector, _) = TestSuite::start().await;
+
+        // the file can not persist the rotation
+        let response = connector
+            .post("/admin/keys")
+            .header(AUTHORIZATION, ADMIN_KEY.as_str())
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+
+        // nothing changed
+        let response = connector.get("/keys").send().await;
+        let body = response.json::<serde_json::Value>().await;
+        assert_eq!(body["keys"].as_array().unwrap().len(), 1);
+    }
+}
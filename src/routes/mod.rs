@@ -23,5 +23,38 @@
  *  SOFTWARE.
  */
 
+use crate::database::client::Client;
+use crate::database::outbox::OutgoingMail;
+use crate::locator::mail::MailOptions;
+use crate::locator::template::MailTemplate;
+use crate::locator::Locator;
+use crate::ROOT;
+
+pub mod application;
 pub mod authentication;
 pub mod client;
+pub mod keys;
+pub mod openid;
+pub mod outbox;
+pub mod webauthn;
+
+/// Queue a security notice about an account event, it always links to the session review
+pub async fn send_security_notice(
+    client: &Client,
+    template: MailTemplate,
+    values: &[(&str, &str)],
+    locator: &Locator,
+) {
+    let link = format!("{}/sessions", ROOT.as_str());
+    let mut values = values.to_vec();
+    values.push(("name", client.preferred_username().as_str()));
+    values.push(("link", link.as_str()));
+
+    let mail = locator
+        .templates()
+        .render(template, client.locale(), values.as_slice());
+    let mail = MailOptions::from_template(client.email().clone(), mail);
+    OutgoingMail::queue(mail, locator.connection())
+        .await
+        .unwrap();
+}
@@ -0,0 +1,871 @@
+/*
+ * The MIT License (MIT)
+ *
+ * Copyright (c) 2022 Randoooom
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in the
+ * Software without restriction, including without limitation the rights to use, copy,
+ * modify, merge, publish, distribute, sublicense, and/or sell copies of the Software,
+ * and to permit persons to whom the Software is furnished to do so, subject to the
+ * following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+ * EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+ * MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+ * NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT
+ * HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR
+ * IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ *  SOFTWARE.
+ */
+
+use crate::database::application::AuthMethod;
+use crate::database::client::Client;
+use crate::error::ResponseError;
+use crate::locator::paseto::TOKEN_LIFETIME;
+use crate::locator::LocatorPointer;
+use crate::openid::client_authentication::{authenticate_application, ClientCredentials};
+use crate::openid::dpop::{proof_bound, verify_proof, DPOP_HEADER};
+use crate::openid::mtls::{certificate_bound, ClientCertificate};
+use crate::ISSUER;
+use axum::extract::Form;
+use axum::http::header::AUTHORIZATION;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::IntoResponse;
+use axum::{Extension, Json};
+use rbatis::crud::CRUD;
+use rbatis::Uuid;
+use std::str::FromStr;
+
+#[derive(Deserialize, Serialize, Default)]
+pub struct TokenRequest {
+    /// only client_credentials is supported
+    grant_type: String,
+    scope: Option<String>,
+    #[serde(flatten)]
+    credentials: ClientCredentials,
+}
+
+pub async fn post_token(
+    headers: HeaderMap,
+    Extension(locator): Extension<LocatorPointer>,
+    certificate: Option<Extension<ClientCertificate>>,
+    Form(request): Form<TokenRequest>,
+) -> impl IntoResponse {
+    // lock the locator
+    let mut locked = locator.lock().await;
+    let certificate = certificate.map(|Extension(certificate)| certificate);
+
+    // authenticate the application
+    let application = authenticate_application(
+        &headers,
+        &request.credentials,
+        certificate.as_ref(),
+        &mut locked,
+    )
+    .await?;
+
+    // verify the proof of possession
+    let pid = match headers.get(DPOP_HEADER) {
+        Some(proof) => {
+            let proof = proof
+                .to_str()
+                .map_err(|_| ResponseError::InvalidDpopProof)?;
+            let uri = format!("{}/token", ISSUER.as_str());
+
+            Some(
+                verify_proof(proof, "POST", uri.as_str(), None, locked.replay_mut())
+                    .ok_or(ResponseError::InvalidDpopProof)?,
+            )
+        }
+        None => None,
+    };
+
+    if request.grant_type != "client_credentials" {
+        return Err(ResponseError::UnsupportedGrantType);
+    }
+    // public clients can not act on their own behalf
+    if application.auth_method() == &AuthMethod::None {
+        return Err(ResponseError::InvalidClient);
+    }
+    let sub = application.client_id().clone();
+    let scope = request.scope;
+
+    let client_id = application.client_id().to_string();
+    let mut claims = json!({ "client_id": client_id, "scope": scope });
+    // bind the token to the client certificate (RFC 8705)
+    if let Some(certificate) = &certificate {
+        claims["cnf"]["x5t#S256"] = json!(certificate.thumbprint());
+    }
+    // bind the token to the key of the proof
+    if let Some(pid) = &pid {
+        claims["cnf"]["kid"] = json!(pid);
+    }
+    let token_type = match pid {
+        Some(_) => "DPoP",
+        None => "Bearer",
+    };
+
+    // sign the access token
+    let token = locked.paseto().sign(&sub, client_id.as_str(), &claims);
+
+    Ok((
+        StatusCode::OK,
+        Json(json!({
+            "access_token": token,
+            "token_type": token_type,
+            "expires_in": TOKEN_LIFETIME * 60,
+            "scope": scope,
+        })),
+    ))
+}
+
+#[derive(Deserialize, Serialize, Default)]
+pub struct IntrospectionRequest {
+    token: String,
+    token_type_hint: Option<String>,
+    /// the method of the protected request (required when forwarding a proof)
+    htm: Option<String>,
+    /// the uri of the protected request (required when forwarding a proof)
+    htu: Option<String>,
+    #[serde(flatten)]
+    credentials: ClientCredentials,
+}
+
+/// Token introspection (RFC 7662) for authenticated applications.
+/// DPoP bound tokens are only reported as active with the proof of the protected request.
+pub async fn post_introspect(
+    headers: HeaderMap,
+    Extension(locator): Extension<LocatorPointer>,
+    certificate: Option<Extension<ClientCertificate>>,
+    Form(request): Form<IntrospectionRequest>,
+) -> impl IntoResponse {
+    // lock the locator
+    let mut locked = locator.lock().await;
+    let certificate = certificate.map(|Extension(certificate)| certificate);
+
+    // authenticate the application
+    authenticate_application(
+        &headers,
+        &request.credentials,
+        certificate.as_ref(),
+        &mut locked,
+    )
+    .await?;
+
+    // verify the token
+    let mut claims = match locked.paseto().verify(request.token.as_str()) {
+        Some(claims) => claims,
+        None => return Ok((StatusCode::OK, Json(json!({"active": false})))),
+    };
+
+    // key bound tokens are only active with a valid proof forwarded by the resource server
+    match headers.get(DPOP_HEADER) {
+        Some(proof) => {
+            let (method, uri) = match (&request.htm, &request.htu) {
+                (Some(method), Some(uri)) => (method, uri),
+                _ => {
+                    return Err(ResponseError::BadRequest(
+                        "htm and htu are required for proofs".into(),
+                    ))
+                }
+            };
+
+            if !proof_bound(
+                &claims,
+                proof.to_str().ok(),
+                method.as_str(),
+                uri.as_str(),
+                request.token.as_str(),
+                locked.replay_mut(),
+            ) {
+                return Ok((StatusCode::OK, Json(json!({"active": false}))));
+            }
+        }
+        None if claims["cnf"]["kid"].is_string() => {
+            return Ok((StatusCode::OK, Json(json!({"active": false}))));
+        }
+        None => {}
+    }
+
+    // the dates are returned as NumericDate
+    for name in ["exp", "iat", "nbf"] {
+        if let Some(date) = claims[name]
+            .as_str()
+            .and_then(|date| chrono::DateTime::parse_from_rfc3339(date).ok())
+        {
+            claims[name] = json!(date.timestamp());
+        }
+    }
+    claims["active"] = json!(true);
+    claims["token_type"] = match claims["cnf"]["kid"].is_string() {
+        true => json!("DPoP"),
+        false => json!("Bearer"),
+    };
+
+    Ok((StatusCode::OK, Json(claims)))
+}
+
+pub async fn get_userinfo(
+    headers: HeaderMap,
+    Extension(locator): Extension<LocatorPointer>,
+    certificate: Option<Extension<ClientCertificate>>,
+) -> impl IntoResponse {
+    // get the token (Bearer or DPoP)
+    let (scheme, token) = headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split_once(' '))
+        .ok_or(ResponseError::Unauthorized)?;
+
+    // lock the locator
+    let mut locked = locator.lock().await;
+    // verify the token
+    let claims = locked
+        .paseto()
+        .verify(token)
+        .ok_or(ResponseError::Unauthorized)?;
+
+    // DPoP bound tokens have to use the DPoP scheme
+    let expected = match claims["cnf"]["kid"].is_string() {
+        true => "DPoP",
+        false => "Bearer",
+    };
+    // verify the possession of the bound key or certificate
+    let uri = format!("{}/userinfo", ISSUER.as_str());
+    if scheme != expected
+        || !certificate_bound(
+            &claims,
+            certificate
+                .as_ref()
+                .map(|Extension(certificate)| certificate),
+        )
+        || !proof_bound(
+            &claims,
+            headers
+                .get(DPOP_HEADER)
+                .and_then(|proof| proof.to_str().ok()),
+            "GET",
+            uri.as_str(),
+            token,
+            locked.replay_mut(),
+        )
+    {
+        return Err(ResponseError::Unauthorized);
+    }
+
+    // get the client
+    let sub = claims["sub"]
+        .as_str()
+        .and_then(|sub| Uuid::from_str(sub).ok())
+        .ok_or(ResponseError::Unauthorized)?;
+    let client: Option<Client> = locked
+        .connection()
+        .fetch_by_column("sub", sub)
+        .await
+        .unwrap();
+
+    Ok((
+        StatusCode::OK,
+        Json(client.ok_or(ResponseError::Unauthorized)?),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::client::hash_password;
+    use crate::locator::paseto::{KeyState, SigningKey};
+    use crate::openid::client_authentication::CLIENT_ASSERTION_TYPE;
+    use crate::openid::dpop::sign_proof;
+    use crate::tests::TestSuite;
+    use crate::{CLIENT_CERT_HEADER, ISSUER};
+    use axum::http::header::{AUTHORIZATION, CONTENT_TYPE};
+    use chrono::{Duration, Utc};
+    use openssl::asn1::Asn1Time;
+    use openssl::hash::MessageDigest;
+    use openssl::pkey::{PKey, Private};
+    use openssl::x509::extension::BasicConstraints;
+    use openssl::x509::{X509NameBuilder, X509};
+    use rusty_paseto::prelude::*;
+
+    /// Encode the given pairs as form body (all values are url safe)
+    fn form(pairs: &[(&str, &str)]) -> String {
+        pairs
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect::<Vec<_>>()
+            .join("&")
+    }
+
+    /// Sign a client assertion for the given client_id
+    fn assertion(pkey: &PKey<Private>, client_id: &str) -> String {
+        let mut bytes = [0u8; 64];
+        bytes[..32].copy_from_slice(pkey.raw_private_key().unwrap().as_slice());
+        bytes[32..].copy_from_slice(pkey.raw_public_key().unwrap().as_slice());
+        let key = Key::<64>::from(bytes);
+        let private_key = PasetoAsymmetricPrivateKey::<V4, Public>::from(key.as_slice());
+
+        let audience = format!("{}/token", ISSUER.as_str());
+        let jti = Uuid::new().to_string();
+        let expiry = (Utc::now() + Duration::minutes(1)).to_rfc3339();
+        PasetoBuilder::<V4, Public>::default()
+            .set_claim(IssuerClaim::from(client_id))
+            .set_claim(SubjectClaim::from(client_id))
+            .set_claim(AudienceClaim::from(audience.as_str()))
+            .set_claim(TokenIdentifierClaim::from(jti.as_str()))
+            .set_claim(ExpirationClaim::try_from(expiry).unwrap())
+            .build(&private_key)
+            .unwrap()
+    }
+
+    /// Build a certificate with the given common name, self signed without issuer
+    fn certificate(
+        common_name: &str,
+        issuer: Option<(&X509, &PKey<Private>)>,
+    ) -> (X509, PKey<Private>) {
+        let pkey = PKey::generate_ed25519().unwrap();
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("O", "Example").unwrap();
+        name.append_entry_by_text("CN", common_name).unwrap();
+        let name = name.build();
+
+        let mut builder = X509::builder().unwrap();
+        builder.set_version(2).unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder.set_pubkey(&pkey).unwrap();
+        builder
+            .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        builder
+            .set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        match issuer {
+            Some((certificate, key)) => {
+                builder.set_issuer_name(certificate.subject_name()).unwrap();
+                builder.sign(key, MessageDigest::null()).unwrap();
+            }
+            None => {
+                // self signed certificates are used as CA as well
+                builder
+                    .append_extension(BasicConstraints::new().critical().ca().build().unwrap())
+                    .unwrap();
+                builder.set_issuer_name(&name).unwrap();
+                builder.sign(&pkey, MessageDigest::null()).unwrap();
+            }
+        }
+
+        (builder.build(), pkey)
+    }
+
+    /// Encode the certificate the way the proxy forwards it
+    fn forwarded(certificate: &X509) -> String {
+        let pem = String::from_utf8(certificate.to_pem().unwrap()).unwrap();
+        percent_encoding::utf8_percent_encode(pem.as_str(), percent_encoding::NON_ALPHANUMERIC)
+            .to_string()
+    }
+
+    #[tokio::test]
+    async fn test_client_secret_basic() {
+        let suite = TestSuite::new().await;
+        let (application, secret) = suite.application(AuthMethod::ClientSecretBasic, None).await;
+
+        // build the basic credentials
+        let basic = base64::encode(format!("{}:{}", application.client_id(), secret.unwrap()));
+
+        let response = suite
+            .connector
+            .post("/token")
+            .header(AUTHORIZATION, format!("Basic {}", basic))
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(form(&[("grant_type", "client_credentials")]))
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = response.json::<serde_json::Value>().await;
+        assert_eq!(body["token_type"], "Bearer");
+        assert!(body["access_token"]
+            .as_str()
+            .unwrap()
+            .starts_with("v4.public."));
+    }
+
+    #[tokio::test]
+    async fn test_client_secret_basic_encoded() {
+        let suite = TestSuite::new().await;
+        let (mut application, _) = suite.application(AuthMethod::ClientSecretBasic, None).await;
+
+        // a secret with reserved characters
+        let secret = "s3cret: +%/";
+        application.set_client_secret(Some(hash_password(secret.to_string())));
+        suite
+            .connection
+            .update_by_column("client_id", &application)
+            .await
+            .unwrap();
+
+        // form-urlencode the parts before joining them
+        let encoded =
+            percent_encoding::utf8_percent_encode(secret, percent_encoding::NON_ALPHANUMERIC)
+                .to_string()
+                .replace("%20", "+");
+        let basic = base64::encode(format!("{}:{}", application.client_id(), encoded));
+
+        let response = suite
+            .connector
+            .post("/token")
+            .header(AUTHORIZATION, format!("Basic {}", basic))
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(form(&[("grant_type", "client_credentials")]))
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // the raw secret does not match after decoding
+        let basic = base64::encode(format!("{}:{}", application.client_id(), secret));
+        let response = suite
+            .connector
+            .post("/token")
+            .header(AUTHORIZATION, format!("Basic {}", basic))
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(form(&[("grant_type", "client_credentials")]))
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_client_secret_post() {
+        let suite = TestSuite::new().await;
+        let (application, secret) = suite.application(AuthMethod::ClientSecretPost, None).await;
+        let client_id = application.client_id().to_string();
+        let secret = secret.unwrap();
+
+        let response = suite
+            .connector
+            .post("/token")
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(form(&[
+                ("grant_type", "client_credentials"),
+                ("client_id", client_id.as_str()),
+                ("client_secret", secret.as_str()),
+            ]))
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // the wrong secret is rejected
+        let response = suite
+            .connector
+            .post("/token")
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(form(&[
+                ("grant_type", "client_credentials"),
+                ("client_id", client_id.as_str()),
+                ("client_secret", "wrong"),
+            ]))
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_unregistered_method() {
+        let suite = TestSuite::new().await;
+        let (application, secret) = suite.application(AuthMethod::ClientSecretBasic, None).await;
+        let client_id = application.client_id().to_string();
+        let secret = secret.unwrap();
+
+        // registered for basic, but uses post
+        let response = suite
+            .connector
+            .post("/token")
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(form(&[
+                ("grant_type", "client_credentials"),
+                ("client_id", client_id.as_str()),
+                ("client_secret", secret.as_str()),
+            ]))
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_private_key_paseto() {
+        let suite = TestSuite::new().await;
+        let pkey = PKey::generate_ed25519().unwrap();
+        let paserk = SigningKey::from_pkey(&pkey, KeyState::Pending).paserk();
+        let (application, _) = suite
+            .application(AuthMethod::PrivateKeyPaseto, Some(paserk))
+            .await;
+        let client_id = application.client_id().to_string();
+
+        let assertion = assertion(&pkey, client_id.as_str());
+        let body = form(&[
+            ("grant_type", "client_credentials"),
+            ("client_id", client_id.as_str()),
+            ("client_assertion_type", CLIENT_ASSERTION_TYPE),
+            ("client_assertion", assertion.as_str()),
+        ]);
+        let response = suite
+            .connector
+            .post("/token")
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(body.clone())
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // the assertion can not be replayed
+        let response = suite
+            .connector
+            .post("/token")
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(body)
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_introspection() {
+        let suite = TestSuite::new().await;
+        let (application, secret) = suite.application(AuthMethod::ClientSecretPost, None).await;
+        let client_id = application.client_id().to_string();
+        let secret = secret.unwrap();
+
+        // get a token
+        let response = suite
+            .connector
+            .post("/token")
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(form(&[
+                ("grant_type", "client_credentials"),
+                ("client_id", client_id.as_str()),
+                ("client_secret", secret.as_str()),
+            ]))
+            .send()
+            .await;
+        let body = response.json::<serde_json::Value>().await;
+        let token = body["access_token"].as_str().unwrap();
+
+        // introspect
+        let response = suite
+            .connector
+            .post("/introspect")
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(form(&[
+                ("token", token),
+                ("client_id", client_id.as_str()),
+                ("client_secret", secret.as_str()),
+            ]))
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.json::<serde_json::Value>().await;
+        assert_eq!(body["active"], true);
+        assert_eq!(body["client_id"], client_id);
+        assert!(body["exp"].is_i64());
+        // no certificate was presented
+        assert!(body["cnf"].is_null());
+
+        // invalid tokens are inactive
+        let response = suite
+            .connector
+            .post("/introspect")
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(form(&[
+                ("token", "v4.public.invalid"),
+                ("client_id", client_id.as_str()),
+                ("client_secret", secret.as_str()),
+            ]))
+            .send()
+            .await;
+        assert_eq!(response.json::<serde_json::Value>().await["active"], false);
+    }
+
+    #[tokio::test]
+    async fn test_tls_client_auth() {
+        let suite = TestSuite::new().await;
+        let connector = suite.behind_proxy();
+
+        // setup the CA
+        let (ca, ca_key) = certificate("ca", None);
+        let path = std::env::temp_dir().join(format!("client-ca-{}.pem", Uuid::new()));
+        std::fs::write(&path, ca.to_pem().unwrap()).unwrap();
+        *suite.locator.lock().await.client_ca_path_mut() = Some(path.to_string_lossy().to_string());
+        let (client_certificate, _) = certificate("client", Some((&ca, &ca_key)));
+        let client_certificate = ClientCertificate(client_certificate);
+
+        // register the subject
+        let (mut application, _) = suite.application(AuthMethod::TlsClientAuth, None).await;
+        application.set_tls_subject_dn(Some(client_certificate.subject_dn()));
+        suite
+            .connection
+            .update_by_column("client_id", &application)
+            .await
+            .unwrap();
+        let client_id = application.client_id().to_string();
+        let body = form(&[
+            ("grant_type", "client_credentials"),
+            ("client_id", client_id.as_str()),
+        ]);
+
+        let response = connector
+            .post("/token")
+            .header(
+                CLIENT_CERT_HEADER.as_str(),
+                forwarded(&client_certificate.0),
+            )
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(body.clone())
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let token = response.json::<serde_json::Value>().await["access_token"]
+            .as_str()
+            .unwrap()
+            .to_string();
+
+        // the token is bound to the certificate
+        let response = connector
+            .post("/introspect")
+            .header(
+                CLIENT_CERT_HEADER.as_str(),
+                forwarded(&client_certificate.0),
+            )
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(form(&[
+                ("token", token.as_str()),
+                ("client_id", client_id.as_str()),
+            ]))
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let introspection = response.json::<serde_json::Value>().await;
+        assert_eq!(
+            introspection["cnf"]["x5t#S256"],
+            client_certificate.thumbprint()
+        );
+
+        // another subject of the same CA is rejected
+        let (other, _) = certificate("other", Some((&ca, &ca_key)));
+        let response = connector
+            .post("/token")
+            .header(CLIENT_CERT_HEADER.as_str(), forwarded(&other))
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(body.clone())
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        // the header is ignored without a trusted proxy
+        let response = suite
+            .connector
+            .post("/token")
+            .header(
+                CLIENT_CERT_HEADER.as_str(),
+                forwarded(&client_certificate.0),
+            )
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(body)
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_self_signed_tls_client_auth() {
+        let suite = TestSuite::new().await;
+        let connector = suite.behind_proxy();
+        let (client_certificate, _) = certificate("client", None);
+        let client_certificate = ClientCertificate(client_certificate);
+
+        // register the thumbprint
+        let (mut application, _) = suite
+            .application(AuthMethod::SelfSignedTlsClientAuth, None)
+            .await;
+        application.set_tls_thumbprint(Some(client_certificate.thumbprint()));
+        suite
+            .connection
+            .update_by_column("client_id", &application)
+            .await
+            .unwrap();
+        let client_id = application.client_id().to_string();
+        let body = form(&[
+            ("grant_type", "client_credentials"),
+            ("client_id", client_id.as_str()),
+        ]);
+
+        let response = connector
+            .post("/token")
+            .header(
+                CLIENT_CERT_HEADER.as_str(),
+                forwarded(&client_certificate.0),
+            )
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(body.clone())
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let token = response.json::<serde_json::Value>().await["access_token"]
+            .as_str()
+            .unwrap()
+            .to_string();
+
+        // the token is bound to the certificate
+        let response = connector
+            .post("/introspect")
+            .header(
+                CLIENT_CERT_HEADER.as_str(),
+                forwarded(&client_certificate.0),
+            )
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(form(&[
+                ("token", token.as_str()),
+                ("client_id", client_id.as_str()),
+            ]))
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let introspection = response.json::<serde_json::Value>().await;
+        assert_eq!(
+            introspection["cnf"]["x5t#S256"],
+            client_certificate.thumbprint()
+        );
+
+        // a different certificate with the same subject is rejected
+        let (other, _) = certificate("client", None);
+        let response = connector
+            .post("/token")
+            .header(CLIENT_CERT_HEADER.as_str(), forwarded(&other))
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(body)
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_dpop_bound_token() {
+        let suite = TestSuite::new().await;
+        let (application, secret) = suite.application(AuthMethod::ClientSecretPost, None).await;
+        let client_id = application.client_id().to_string();
+        let secret = secret.unwrap();
+        let pkey = PKey::generate_ed25519().unwrap();
+
+        // request a token with a proof
+        let proof = sign_proof(
+            &pkey,
+            "POST",
+            format!("{}/token", ISSUER.as_str()).as_str(),
+            None,
+        );
+        let response = suite
+            .connector
+            .post("/token")
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .header(DPOP_HEADER, proof)
+            .body(form(&[
+                ("grant_type", "client_credentials"),
+                ("client_id", client_id.as_str()),
+                ("client_secret", secret.as_str()),
+            ]))
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.json::<serde_json::Value>().await;
+        assert_eq!(body["token_type"], "DPoP");
+        let access_token = body["access_token"].as_str().unwrap();
+
+        // introspection without the proof of the protected request
+        let introspection = form(&[
+            ("token", access_token),
+            ("client_id", client_id.as_str()),
+            ("client_secret", secret.as_str()),
+            ("htm", "GET"),
+            ("htu", "https://api.example.com/resource"),
+        ]);
+        let response = suite
+            .connector
+            .post("/introspect")
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(introspection.clone())
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.json::<serde_json::Value>().await["active"], false);
+
+        // with the forwarded proof
+        let proof = sign_proof(
+            &pkey,
+            "GET",
+            "https://api.example.com/resource",
+            Some(access_token),
+        );
+        let response = suite
+            .connector
+            .post("/introspect")
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .header(DPOP_HEADER, proof)
+            .body(introspection)
+            .send()
+            .await;
+        let introspected = response.json::<serde_json::Value>().await;
+        assert_eq!(introspected["active"], true);
+        assert_eq!(introspected["token_type"], "DPoP");
+
+        // a user token bound to the same key
+        let token = {
+            let locked = suite.locator.lock().await;
+            let claims = locked.paseto().verify(access_token).unwrap();
+            assert!(claims["cnf"]["kid"].is_string());
+
+            locked.paseto().sign(
+                suite.client.sub(),
+                client_id.as_str(),
+                &json!({ "cnf": claims["cnf"] }),
+            )
+        };
+
+        // the token can not be used without a proof
+        let response = suite
+            .connector
+            .get("/userinfo")
+            .header(AUTHORIZATION, format!("DPoP {}", token))
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        // with a valid proof
+        let uri = format!("{}/userinfo", ISSUER.as_str());
+        let proof = sign_proof(&pkey, "GET", uri.as_str(), Some(token.as_str()));
+        let response = suite
+            .connector
+            .get("/userinfo")
+            .header(AUTHORIZATION, format!("DPoP {}", token))
+            .header(DPOP_HEADER, proof.clone())
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.json::<Client>().await.sub(), suite.client.sub());
+
+        // the proof can not be replayed
+        let response = suite
+            .connector
+            .get("/userinfo")
+            .header(AUTHORIZATION, format!("DPoP {}", token))
+            .header(DPOP_HEADER, proof)
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+}
@@ -0,0 +1,117 @@
+/*
+ * The MIT License (MIT)
+ *
+ * Copyright (c) 2022 Randoooom
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in the
+ * Software without restriction, including without limitation the rights to use, copy,
+ * modify, merge, publish, distribute, sublicense, and/or sell copies of the Software,
+ * and to permit persons to whom the Software is furnished to do so, subject to the
+ * following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+ * EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+ * MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+ * NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT
+ * HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR
+ * IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ *  SOFTWARE.
+ */
+
+use crate::database::outbox::{MailStatus, OutgoingMail};
+use crate::locator::LocatorPointer;
+use axum::extract::Query;
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use axum::{Extension, Json};
+use rbatis::crud::CRUD;
+
+#[derive(Deserialize, Serialize)]
+pub struct OutboxFilter {
+    /// only list the mails with this status
+    status: Option<MailStatus>,
+}
+
+/// List the delivery status of the queued mails
+pub async fn get_outbox(
+    Extension(locator): Extension<LocatorPointer>,
+    Query(filter): Query<OutboxFilter>,
+) -> impl IntoResponse {
+    // lock the locator
+    let locked = locator.lock().await;
+
+    let mails: Vec<OutgoingMail> = match filter.status {
+        Some(status) => locked
+            .connection()
+            .fetch_list_by_column("status", &[status])
+            .await
+            .unwrap(),
+        None => locked.connection().fetch_list().await.unwrap(),
+    };
+
+    // the content is not required to report the status
+    let mails = mails
+        .iter()
+        .map(|mail| {
+            json!({
+                "uuid": mail.uuid(),
+                "recipient": mail.recipient(),
+                "subject": mail.subject(),
+                "status": mail.status(),
+                "attempts": mail.attempts(),
+                "last_error": mail.last_error(),
+                "next_attempt": mail.next_attempt(),
+                "sent_at": mail.sent_at(),
+                "created_at": mail.created_at(),
+            })
+        })
+        .collect::<Vec<_>>();
+
+    (StatusCode::OK, Json(json!({ "mails": mails })))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::database::outbox::OutgoingMail;
+    use crate::locator::mail::MailOptions;
+    use crate::tests::TestSuite;
+    use crate::ADMIN_KEY;
+    use axum::http::header::AUTHORIZATION;
+    use axum::http::StatusCode;
+
+    #[tokio::test]
+    async fn test_outbox() {
+        let (connector, connection) = TestSuite::start().await;
+
+        // queue a mail
+        let options = MailOptions::builder()
+            .to("test@example.com".to_string())
+            .subject("Subject".to_string())
+            .html("<p>Content</p>".to_string())
+            .text("Content".to_string())
+            .build();
+        let mail = OutgoingMail::queue(options, &connection).await.unwrap();
+
+        // unauthorized without the admin key
+        let response = connector.get("/admin/outbox").send().await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = connector
+            .get("/admin/outbox?status=Pending")
+            .header(AUTHORIZATION, ADMIN_KEY.as_str())
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.json::<serde_json::Value>().await;
+        assert!(body["mails"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .any(|queued| queued["uuid"] == mail.uuid().to_string()));
+    }
+}
@@ -0,0 +1,574 @@
+/*
+ * The MIT License (MIT)
+ *
+ * Copyright (c) 2022 Randoooom
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in the
+ * Software without restriction, including without limitation the rights to use, copy,
+ * modify, merge, publish, distribute, sublicense, and/or sell copies of the Software,
+ * and to permit persons to whom the Software is furnished to do so, subject to the
+ * following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+ * EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+ * MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+ * NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT
+ * HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR
+ * IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ *  SOFTWARE.
+ */
+
+use crate::database::client::Client;
+use crate::database::credential::WebauthnCredential;
+use crate::error::ResponseError;
+use crate::locator::template::MailTemplate;
+use crate::locator::webauthn::{AuthenticationCredential, RegistrationCredential};
+use crate::locator::{Locator, LocatorPointer};
+use crate::middleware::ClientAddress;
+use crate::routes::authentication::{
+    attempt_keys, check_locks, notify_login, record_failure, record_success,
+};
+use crate::routes::send_security_notice;
+use axum::extract::Path;
+use axum::http::{HeaderMap, StatusCode};
+use axum::response::IntoResponse;
+use axum::{Extension, Json};
+use rbatis::crud::CRUD;
+use rbatis::{TimestampZ, Uuid};
+use std::str::FromStr;
+
+/// The view of a credential returned to its owner
+fn view(credential: &WebauthnCredential) -> serde_json::Value {
+    json!({
+        "uuid": credential.uuid(),
+        "name": credential.name(),
+        "transports": credential.transport_list(),
+        "created_at": credential.created_at(),
+        "last_used": credential.last_used(),
+    })
+}
+
+/// Verify the assertion with the stored credential and record its usage
+async fn authenticate(
+    assertion: &AuthenticationCredential,
+    mut stored: WebauthnCredential,
+    require_user_verification: bool,
+    locator: &mut Locator,
+) -> bool {
+    if locator
+        .webauthn_mut()
+        .verify_authentication(assertion, &mut stored, require_user_verification)
+        .is_err()
+    {
+        return false;
+    }
+
+    stored.set_last_used(Some(TimestampZ::now()));
+    locator
+        .connection()
+        .update_by_column("uuid", &stored)
+        .await
+        .unwrap();
+    true
+}
+
+/// Verify the assertion of one of the given credentials as second factor
+pub async fn verify_second_factor(
+    assertion: &AuthenticationCredential,
+    credentials: Vec<WebauthnCredential>,
+    locator: &mut Locator,
+) -> bool {
+    match credentials
+        .into_iter()
+        .find(|credential| credential.credential_id() == assertion.id())
+    {
+        Some(stored) => authenticate(assertion, stored, false, locator).await,
+        None => false,
+    }
+}
+
+pub async fn post_start_registration(
+    Extension(locator): Extension<LocatorPointer>,
+    Extension(client): Extension<Client>,
+) -> impl IntoResponse {
+    // lock the locator
+    let mut locked = locator.lock().await;
+
+    // the registered authenticators are excluded
+    let existing = WebauthnCredential::of_client(client.sub(), locked.connection())
+        .await
+        .unwrap();
+    let options = locked
+        .webauthn_mut()
+        .creation_options(&client, existing.as_slice());
+
+    (StatusCode::OK, Json(options))
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct FinishRegistration {
+    /// the displayable name of the authenticator
+    name: String,
+    credential: RegistrationCredential,
+}
+
+pub async fn post_finish_registration(
+    Extension(locator): Extension<LocatorPointer>,
+    Extension(client): Extension<Client>,
+    Json(data): Json<FinishRegistration>,
+) -> Result<impl IntoResponse, ResponseError> {
+    // lock the locator
+    let mut locked = locator.lock().await;
+
+    let credential = locked
+        .webauthn_mut()
+        .verify_registration(client.sub(), data.name, &data.credential)
+        .map_err(|error| ResponseError::BadRequest(error.to_string()))?;
+
+    // every authenticator can only be registered once
+    let connection = locked.connection();
+    if WebauthnCredential::from_credential_id(credential.credential_id(), connection)
+        .await
+        .unwrap()
+        .is_some()
+    {
+        return Err(ResponseError::Conflict(
+            "Authenticator already registered".into(),
+        ));
+    }
+    connection.save(&credential, &[]).await.unwrap();
+    send_security_notice(
+        &client,
+        MailTemplate::AuthenticatorAdded,
+        &[("authenticator", credential.name().as_str())],
+        &locked,
+    )
+    .await;
+
+    Ok((StatusCode::CREATED, Json(view(&credential))))
+}
+
+/// Discoverable credential options for the passwordless login. The authenticators of a
+/// client are only revealed by `/auth/login` after the password has been verified.
+pub async fn post_start_authentication(
+    Extension(locator): Extension<LocatorPointer>,
+) -> impl IntoResponse {
+    // lock the locator
+    let mut locked = locator.lock().await;
+
+    let options = locked.webauthn_mut().request_options(None, &[]);
+    (StatusCode::OK, Json(options))
+}
+
+/// Passwordless login with a passkey
+pub async fn post_finish_authentication(
+    Extension(locator): Extension<LocatorPointer>,
+    address: Option<Extension<ClientAddress>>,
+    headers: HeaderMap,
+    Json(assertion): Json<AuthenticationCredential>,
+) -> Result<impl IntoResponse, ResponseError> {
+    // lock the locator
+    let mut locked = locator.lock().await;
+
+    let stored = WebauthnCredential::from_credential_id(assertion.id(), locked.connection())
+        .await
+        .unwrap();
+    let client: Option<Client> = match &stored {
+        Some(stored) => locked
+            .connection()
+            .fetch_by_column("sub", stored.client())
+            .await
+            .unwrap(),
+        None => None,
+    };
+
+    // the passkey is subject to the same locks as the password login
+    let keys = attempt_keys(
+        client.as_ref(),
+        address.as_ref().map(|Extension(address)| address),
+    );
+    check_locks(keys.as_slice(), locked.connection()).await?;
+
+    // the user verification makes the passkey a multi-factor method on its own
+    let verified = match (stored, &client) {
+        (Some(stored), Some(_)) => authenticate(&assertion, stored, true, &mut locked).await,
+        _ => false,
+    };
+    let client = match (verified, client) {
+        (true, Some(client)) => client,
+        (_, client) => {
+            record_failure(keys, client.as_ref(), &locked).await;
+            return Err(ResponseError::Unauthorized);
+        }
+    };
+    record_success(&client, locked.connection()).await;
+
+    // start the session
+    let session = locked.auth_mut().start_session(
+        client.sub().clone(),
+        vec!["hwk".to_string(), "mfa".to_string()],
+    );
+    notify_login(&client, address, &headers, &locked).await;
+
+    Ok((StatusCode::OK, Json(json!({ "session_id": session }))))
+}
+
+pub async fn get_authenticators(
+    Extension(locator): Extension<LocatorPointer>,
+    Extension(client): Extension<Client>,
+) -> impl IntoResponse {
+    // lock the locator
+    let locked = locator.lock().await;
+
+    let authenticators = WebauthnCredential::of_client(client.sub(), locked.connection())
+        .await
+        .unwrap()
+        .iter()
+        .map(view)
+        .collect::<Vec<serde_json::Value>>();
+
+    (
+        StatusCode::OK,
+        Json(json!({ "authenticators": authenticators })),
+    )
+}
+
+/// Get the credential with the given uuid, if it belongs to the client
+async fn owned_credential(
+    uuid: &str,
+    client: &Client,
+    locator: &Locator,
+) -> Result<WebauthnCredential, ResponseError> {
+    let uuid =
+        Uuid::from_str(uuid).map_err(|_| ResponseError::BadRequest("Invalid uuid".into()))?;
+    let credential: Option<WebauthnCredential> = locator
+        .connection()
+        .fetch_by_column("uuid", &uuid)
+        .await
+        .unwrap();
+
+    credential
+        .filter(|credential| credential.client() == client.sub())
+        .ok_or_else(|| ResponseError::BadRequest("Unknown authenticator".into()))
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct RenameAuthenticator {
+    name: String,
+}
+
+pub async fn put_authenticator(
+    Extension(locator): Extension<LocatorPointer>,
+    Extension(client): Extension<Client>,
+    Path(uuid): Path<String>,
+    Json(data): Json<RenameAuthenticator>,
+) -> Result<impl IntoResponse, ResponseError> {
+    // lock the locator
+    let locked = locator.lock().await;
+
+    let mut credential = owned_credential(uuid.as_str(), &client, &locked).await?;
+    credential.set_name(data.name);
+    locked
+        .connection()
+        .update_by_column("uuid", &credential)
+        .await
+        .unwrap();
+
+    Ok((StatusCode::OK, Json(view(&credential))))
+}
+
+pub async fn post_delete_authenticator(
+    Extension(locator): Extension<LocatorPointer>,
+    Extension(client): Extension<Client>,
+    Path(uuid): Path<String>,
+) -> Result<impl IntoResponse, ResponseError> {
+    // lock the locator
+    let locked = locator.lock().await;
+
+    let credential = owned_credential(uuid.as_str(), &client, &locked).await?;
+    locked
+        .connection()
+        .remove_by_column::<WebauthnCredential, _>("uuid", credential.uuid())
+        .await
+        .unwrap();
+    send_security_notice(
+        &client,
+        MailTemplate::AuthenticatorRemoved,
+        &[("authenticator", credential.name().as_str())],
+        &locked,
+    )
+    .await;
+
+    Ok((StatusCode::OK, Json(json!({"message": "Removed"}))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::attempts::LoginAttempts;
+    use crate::locator::webauthn::SoftAuthenticator;
+    use crate::routes::authentication::AuthenticationRequest;
+    use crate::tests::TestSuite;
+    use crate::WEBAUTHN_ORIGIN;
+    use axum::http::header::AUTHORIZATION;
+
+    /// Register a new software authenticator for the default client
+    async fn register(suite: &TestSuite, authorization: &str) -> SoftAuthenticator {
+        let mut authenticator = SoftAuthenticator::new();
+
+        let response = suite
+            .connector
+            .post("/auth/webauthn/register/start")
+            .header(AUTHORIZATION, authorization)
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let options = response.json::<serde_json::Value>().await;
+
+        let body = FinishRegistration {
+            name: "Laptop".to_string(),
+            credential: authenticator.create(&options, WEBAUTHN_ORIGIN.as_str()),
+        };
+        let response = suite
+            .connector
+            .post("/auth/webauthn/register/finish")
+            .json(&body)
+            .header(AUTHORIZATION, authorization)
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+
+        authenticator
+    }
+
+    /// Get the discoverable request options
+    async fn request_options(suite: &TestSuite) -> serde_json::Value {
+        let response = suite
+            .connector
+            .post("/auth/webauthn/login/start")
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        response.json::<serde_json::Value>().await
+    }
+
+    #[tokio::test]
+    async fn test_passkey_login() {
+        let suite = TestSuite::new().await;
+        let authorization = suite.authenticate("dfclient", "password").await;
+        let mut authenticator = register(&suite, authorization.as_str()).await;
+
+        // passwordless
+        let options = request_options(&suite).await;
+        let assertion = authenticator.get(&options, WEBAUTHN_ORIGIN.as_str());
+        let response = suite
+            .connector
+            .post("/auth/webauthn/login/finish")
+            .json(&assertion)
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // the challenge can not be reused
+        let response = suite
+            .connector
+            .post("/auth/webauthn/login/finish")
+            .json(&assertion)
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        // the user verification is required without password
+        authenticator.user_verification = false;
+        let options = request_options(&suite).await;
+        let assertion = authenticator.get(&options, WEBAUTHN_ORIGIN.as_str());
+        let response = suite
+            .connector
+            .post("/auth/webauthn/login/finish")
+            .json(&assertion)
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_start_hides_credentials() {
+        let suite = TestSuite::new().await;
+        let authorization = suite.authenticate("dfclient", "password").await;
+        register(&suite, authorization.as_str()).await;
+
+        // known and unknown identifiers get the same discoverable options
+        for identifier in ["dfclient", "unknown"] {
+            let response = suite
+                .connector
+                .post("/auth/webauthn/login/start")
+                .json(&json!({ "identifier": identifier }))
+                .send()
+                .await;
+            assert_eq!(response.status(), StatusCode::OK);
+            let options = response.json::<serde_json::Value>().await;
+            assert!(options["allowCredentials"].as_array().unwrap().is_empty());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_passkey_login_locked() {
+        let suite = TestSuite::new().await;
+        let authorization = suite.authenticate("dfclient", "password").await;
+        let mut authenticator = register(&suite, authorization.as_str()).await;
+
+        // lock the account
+        let key = LoginAttempts::client_key(suite.client.sub());
+        for _ in 0..*crate::LOGIN_LOCK_AFTER {
+            LoginAttempts::fail(key.clone(), &suite.connection)
+                .await
+                .unwrap();
+        }
+
+        let options = request_options(&suite).await;
+        let response = suite
+            .connector
+            .post("/auth/webauthn/login/finish")
+            .json(&authenticator.get(&options, WEBAUTHN_ORIGIN.as_str()))
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    #[tokio::test]
+    async fn test_passkey_login_failure() {
+        let suite = TestSuite::new().await;
+        let authorization = suite.authenticate("dfclient", "password").await;
+        let mut authenticator = register(&suite, authorization.as_str()).await;
+
+        // the user verification is missing
+        authenticator.user_verification = false;
+        let options = request_options(&suite).await;
+        let response = suite
+            .connector
+            .post("/auth/webauthn/login/finish")
+            .json(&authenticator.get(&options, WEBAUTHN_ORIGIN.as_str()))
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        // the failure is recorded for the account
+        let attempts = LoginAttempts::get(
+            LoginAttempts::client_key(suite.client.sub()).as_str(),
+            &suite.connection,
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(*attempts.failures(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_second_factor() {
+        let suite = TestSuite::new().await;
+        let authorization = suite.authenticate("dfclient", "password").await;
+        let mut authenticator = register(&suite, authorization.as_str()).await;
+        authenticator.user_verification = false;
+
+        // the password alone is not enough anymore
+        let body = AuthenticationRequest::builder()
+            .identifier("dfclient".into())
+            .password("password".into())
+            .token(None)
+            .build();
+        let response = suite.connector.post("/auth/login").json(&body).send().await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        // the options are limited to the authenticators of the client
+        let body = response.json::<serde_json::Value>().await;
+        assert_eq!(body["error"], "second_factor_required");
+        let options = &body["webauthn"];
+        assert_eq!(options["allowCredentials"].as_array().unwrap().len(), 1);
+
+        let body = AuthenticationRequest::builder()
+            .identifier("dfclient".into())
+            .password("password".into())
+            .token(None)
+            .webauthn(Some(authenticator.get(options, WEBAUTHN_ORIGIN.as_str())))
+            .build();
+        let response = suite.connector.post("/auth/login").json(&body).send().await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_manage() {
+        let suite = TestSuite::new().await;
+        let authorization = suite.authenticate("dfclient", "password").await;
+        register(&suite, authorization.as_str()).await;
+
+        // list
+        let response = suite
+            .connector
+            .get("/client/me/authenticators")
+            .header(AUTHORIZATION, &authorization)
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.json::<serde_json::Value>().await;
+        let authenticator = &body["authenticators"][0];
+        assert_eq!(authenticator["name"], "Laptop");
+        let uuid = authenticator["uuid"].as_str().unwrap();
+
+        // rename
+        let response = suite
+            .connector
+            .put(format!("/client/me/authenticators/{}", uuid).as_str())
+            .json(&RenameAuthenticator {
+                name: "Phone".to_string(),
+            })
+            .header(AUTHORIZATION, &authorization)
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = response.json::<serde_json::Value>().await;
+        assert_eq!(body["name"], "Phone");
+
+        // delete
+        let response = suite
+            .connector
+            .post(format!("/client/me/authenticators/{}/delete", uuid).as_str())
+            .header(AUTHORIZATION, &authorization)
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let credentials = WebauthnCredential::of_client(suite.client.sub(), &suite.connection)
+            .await
+            .unwrap();
+        assert!(credentials.is_empty());
+
+        // the password is enough again
+        suite.authenticate("dfclient", "password").await;
+    }
+
+    #[tokio::test]
+    async fn test_delete_client() {
+        let suite = TestSuite::new().await;
+        let authorization = suite.authenticate("dfclient", "password").await;
+        register(&suite, authorization.as_str()).await;
+
+        let response = suite
+            .connector
+            .post("/client/delete")
+            .header(AUTHORIZATION, &authorization)
+            .send()
+            .await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // the credentials are removed with the client
+        let credentials = WebauthnCredential::of_client(suite.client.sub(), &suite.connection)
+            .await
+            .unwrap();
+        assert!(credentials.is_empty());
+    }
+}
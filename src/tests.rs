@@ -23,13 +23,21 @@
  *  SOFTWARE.
  */
 
-use crate::app;
+use crate::database::application::{Application, AuthMethod};
 use crate::database::client::{Address, Client, ClientAuthenticationData};
 use crate::database::establish_connection;
+use crate::locator::mail::{MailOptions, MailSender, MemoryBackend};
+use crate::locator::{Locator, LocatorPointer};
+use crate::router;
+use crate::worker::MailWorker;
+use crate::TRUSTED_PROXIES;
+use axum::extract::ConnectInfo;
 use axum::http::StatusCode;
+use axum::Extension;
 use axum_test_helper::TestClient;
 use rbatis::crud::CRUD;
 use rbatis::rbatis::Rbatis;
+use std::net::SocketAddr;
 
 #[cfg(test)]
 pub struct TestSuite {
@@ -37,20 +45,29 @@ pub struct TestSuite {
     pub connection: Rbatis,
     pub connector: TestClient,
     pub authentication_data: ClientAuthenticationData,
+    pub locator: LocatorPointer,
 }
 
 #[cfg(test)]
 impl TestSuite {
     pub async fn start() -> (TestClient, Rbatis) {
+        let (connector, connection, _) = Self::start_with_locator().await;
+
+        (connector, connection)
+    }
+
+    /// Start with access to the locator of the app
+    pub async fn start_with_locator() -> (TestClient, Rbatis, LocatorPointer) {
         // build the testClient
-        let connector = TestClient::new(app().await);
+        let locator = Locator::new().await;
+        let connector = TestClient::new(router(locator.clone()));
         // connect to the database
         let connection = establish_connection().await;
 
         // reset the database
         Self::reset_database(&connection).await;
 
-        (connector, connection)
+        (connector, connection, locator)
     }
 
     pub async fn reset_database(connection: &Rbatis) {
@@ -62,7 +79,7 @@ impl TestSuite {
     /// Create a new suite
     pub async fn new() -> Self {
         // setup
-        let (connector, connection) = Self::start().await;
+        let (connector, connection, locator) = Self::start_with_locator().await;
 
         // create a new client
         let client = Client::default();
@@ -92,15 +109,27 @@ impl TestSuite {
             connection,
             connector,
             authentication_data: auth,
+            locator,
         }
     }
 
-    pub async fn authenticate(&self, nickname: &str, password: &str) -> String {
+    /// Build a connector whose requests arrive from the first trusted proxy
+    pub fn behind_proxy(&self) -> TestClient {
+        let proxy = TRUSTED_PROXIES
+            .first()
+            .expect("TRUSTED_PROXIES has to be set for the tests");
+
+        TestClient::new(
+            router(self.locator.clone()).layer(Extension(ConnectInfo(SocketAddr::new(*proxy, 0)))),
+        )
+    }
+
+    pub async fn authenticate(&self, identifier: &str, password: &str) -> String {
         // send the authentication request
         let response = self
             .connector
             .post("/auth/login")
-            .json(&json!({"nickname": nickname,
+            .json(&json!({"identifier": identifier,
             "password": password}))
             .send()
             .await;
@@ -116,4 +145,54 @@ impl TestSuite {
         // convert
         session_id.as_str().unwrap().to_string()
     }
+
+    /// Register a new application and return it with its plain client secret
+    pub async fn application(
+        &self,
+        method: AuthMethod,
+        public_key: Option<String>,
+    ) -> (Application, Option<String>) {
+        // only the secret based methods get a secret
+        let secret = match method {
+            AuthMethod::ClientSecretBasic | AuthMethod::ClientSecretPost => {
+                Some(Application::gen_secret())
+            }
+            _ => None,
+        };
+
+        let application = Application::builder()
+            .name("Test Application")
+            .redirect_uri("https://app.example.com/callback")
+            .client_secret(secret.clone())
+            .auth_method(method)
+            .public_key(public_key)
+            .build();
+        self.connection.save(&application, &[]).await.unwrap();
+
+        (application, secret)
+    }
+
+    /// Deliver the queued mails and return them
+    pub async fn deliver() -> Vec<MailOptions> {
+        let backend = MemoryBackend::default();
+        let mail = MailSender::with_transport(
+            "server@example.com".parse().unwrap(),
+            Box::new(backend.clone()),
+        );
+
+        MailWorker::new(establish_connection().await, mail)
+            .process()
+            .await
+            .unwrap();
+        backend.mails()
+    }
+
+    /// Extract the token of the first link in the mail
+    pub fn token(mail: &MailOptions) -> String {
+        let start = mail.text().find("token=").unwrap() + "token=".len();
+        mail.text()[start..]
+            .chars()
+            .take_while(|char| !char.is_whitespace())
+            .collect()
+    }
 }
@@ -0,0 +1,96 @@
+/*
+ * The MIT License (MIT)
+ *
+ * Copyright (c) 2022 Randoooom
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in the
+ * Software without restriction, including without limitation the rights to use, copy,
+ * modify, merge, publish, distribute, sublicense, and/or sell copies of the Software,
+ * and to permit persons to whom the Software is furnished to do so, subject to the
+ * following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+ * EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+ * MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+ * NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT
+ * HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR
+ * IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ *  SOFTWARE.
+ */
+
+use crate::openid::mtls::ClientCertificate;
+use axum::extract::ConnectInfo;
+use axum::Router;
+use hyper::server::conn::Http;
+use hyper::service::service_fn;
+use openssl::ssl::{Ssl, SslAcceptor, SslFiletype, SslMethod, SslVerifyMode};
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio_openssl::SslStream;
+use tower::ServiceExt;
+
+/// Build the acceptor from the configured certificate and key.
+/// Client certificates are requested, but verified by the authentication methods,
+/// because self signed certificates are allowed too.
+fn acceptor(certificate: &str, key: &str) -> SslAcceptor {
+    let mut acceptor = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls()).unwrap();
+    acceptor
+        .set_private_key_file(key, SslFiletype::PEM)
+        .expect("Load the tls private key");
+    acceptor
+        .set_certificate_chain_file(certificate)
+        .expect("Load the tls certificate");
+    acceptor.set_verify_callback(SslVerifyMode::PEER, |_, _| true);
+
+    acceptor.build()
+}
+
+/// Serve the app over tls and pass the client certificates to the handlers
+pub async fn serve(app: Router, address: SocketAddr, certificate: &str, key: &str) {
+    let acceptor = Arc::new(acceptor(certificate, key));
+    let listener = TcpListener::bind(address).await.unwrap();
+
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(connection) => connection,
+            Err(error) => {
+                warn!("Failed to accept connection: {}", error);
+                continue;
+            }
+        };
+        let acceptor = acceptor.clone();
+        let app = app.clone();
+
+        tokio::spawn(async move {
+            // handshake
+            let ssl = Ssl::new(acceptor.context()).unwrap();
+            let mut stream = SslStream::new(ssl, stream).unwrap();
+            if let Err(error) = Pin::new(&mut stream).accept().await {
+                debug!("Tls handshake with {} failed: {}", peer, error);
+                return;
+            }
+
+            // the certificate presented by the client
+            let certificate = stream.ssl().peer_certificate().map(ClientCertificate);
+            let service = service_fn(move |mut request| {
+                request.extensions_mut().insert(ConnectInfo(peer));
+                if let Some(certificate) = certificate.clone() {
+                    request.extensions_mut().insert(certificate);
+                }
+
+                app.clone().oneshot(request)
+            });
+
+            if let Err(error) = Http::new().serve_connection(stream, service).await {
+                debug!("Connection with {} failed: {}", peer, error);
+            }
+        });
+    }
+}
@@ -0,0 +1,82 @@
+/*
+ * The MIT License (MIT)
+ *
+ * Copyright (c) 2022 Randoooom
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy of
+ * this software and associated documentation files (the "Software"), to deal in the
+ * Software without restriction, including without limitation the rights to use, copy,
+ * modify, merge, publish, distribute, sublicense, and/or sell copies of the Software,
+ * and to permit persons to whom the Software is furnished to do so, subject to the
+ * following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in all
+ * copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND,
+ * EXPRESS OR IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF
+ * MERCHANTABILITY, FITNESS FOR A PARTICULAR PURPOSE AND
+ * NONINFRINGEMENT. IN NO EVENT SHALL THE AUTHORS OR COPYRIGHT
+ * HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER LIABILITY, WHETHER
+ * IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM, OUT OF OR
+ * IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ *  SOFTWARE.
+ */
+
+use crate::database::outbox::OutgoingMail;
+use crate::locator::mail::MailSender;
+use crate::{MAIL_MAX_ATTEMPTS, MAIL_POLL_INTERVAL};
+use rbatis::crud::CRUD;
+use rbatis::rbatis::Rbatis;
+use std::time::Duration;
+
+/// Delivers the mails of the outbox in the background
+pub struct MailWorker {
+    connection: Rbatis,
+    mail: MailSender,
+}
+
+impl MailWorker {
+    /// Create new MailWorker (it should use its own connection, not the one of the locator)
+    pub fn new(connection: Rbatis, mail: MailSender) -> Self {
+        Self { connection, mail }
+    }
+
+    /// Deliver the due mails forever
+    pub async fn run(self) {
+        let mut interval = tokio::time::interval(Duration::from_secs(*MAIL_POLL_INTERVAL));
+
+        loop {
+            interval.tick().await;
+            if let Err(error) = self.process().await {
+                error!("Failed to process the mail outbox: {}", error);
+            }
+        }
+    }
+
+    /// Try to deliver all due mails once
+    pub async fn process(&self) -> rbatis::Result<()> {
+        for mut mail in OutgoingMail::due(&self.connection).await? {
+            match self.mail.send(mail.options()).await {
+                Ok(_) => {
+                    mail.sent();
+                    info!("Delivered mail {} to {}", mail.uuid(), mail.recipient());
+                }
+                Err(error) => {
+                    mail.failed(error.to_string(), *MAIL_MAX_ATTEMPTS);
+                    warn!(
+                        "Failed to deliver mail {} ({:?} after {} attempts): {}",
+                        mail.uuid(),
+                        mail.status(),
+                        mail.attempts(),
+                        error
+                    );
+                }
+            }
+
+            self.connection.update_by_column("uuid", &mail).await?;
+        }
+
+        Ok(())
+    }
+}